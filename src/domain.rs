@@ -0,0 +1,312 @@
+use std::{
+    collections::HashSet,
+    fmt::{self, Debug},
+};
+
+use crate::enums::Tile;
+
+const WORD_BITS: usize = 64;
+
+/// The tiles a cell can still be, stored as a bitset: bit `i` is set when `Tile(i)`
+/// is possible. Up to 64 tiles fit in a single word, more spill into a vector.
+///
+/// Iteration is always in increasing tile order.
+#[derive(Clone)]
+pub enum Domain {
+    Word(u64),
+    Words(Vec<u64>),
+}
+
+impl Default for Domain {
+    fn default() -> Self {
+        Domain::Word(0)
+    }
+}
+
+impl Domain {
+    pub fn new() -> Self {
+        Domain::default()
+    }
+
+    /// An empty domain able to hold `Tile(0)` to `Tile(tile_count - 1)` without growing.
+    pub fn with_capacity(tile_count: usize) -> Self {
+        if tile_count <= WORD_BITS {
+            Domain::Word(0)
+        } else {
+            Domain::Words(vec![0; tile_count.div_ceil(WORD_BITS)])
+        }
+    }
+
+    fn words(&self) -> &[u64] {
+        match self {
+            Domain::Word(word) => std::slice::from_ref(word),
+            Domain::Words(words) => words,
+        }
+    }
+
+    fn words_mut(&mut self) -> &mut [u64] {
+        match self {
+            Domain::Word(word) => std::slice::from_mut(word),
+            Domain::Words(words) => words,
+        }
+    }
+
+    fn grow(&mut self, word_count: usize) {
+        if word_count <= self.words().len() {
+            return;
+        }
+        let mut words = self.words().to_vec();
+        words.resize(word_count, 0);
+        *self = Domain::Words(words);
+    }
+
+    pub fn insert(&mut self, tile: Tile) -> bool {
+        let (word, bit) = position(&tile);
+        self.grow(word + 1);
+        let words = self.words_mut();
+        let inserted = words[word] & bit == 0;
+        words[word] |= bit;
+        inserted
+    }
+
+    pub fn remove(&mut self, tile: &Tile) -> bool {
+        let (word, bit) = position(tile);
+        match self.words_mut().get_mut(word) {
+            Some(word) if *word & bit != 0 => {
+                *word &= !bit;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn contains(&self, tile: &Tile) -> bool {
+        let (word, bit) = position(tile);
+        self.words().get(word).is_some_and(|word| word & bit != 0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.words()
+            .iter()
+            .map(|word| word.count_ones() as usize)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words().iter().all(|word| *word == 0)
+    }
+
+    /// The only tile of a collapsed cell.
+    pub fn single(&self) -> Option<Tile> {
+        if self.len() == 1 {
+            self.iter().next()
+        } else {
+            None
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            words: self.words(),
+            index: 0,
+            current: self.words().first().copied().unwrap_or(0),
+        }
+    }
+
+    /// Keeps only the tiles also in `other`.
+    pub fn intersect_with(&mut self, other: &Domain) {
+        let other = other.words();
+        for (i, word) in self.words_mut().iter_mut().enumerate() {
+            *word &= other.get(i).copied().unwrap_or(0);
+        }
+    }
+
+    pub fn union_with(&mut self, other: &Domain) {
+        let other = other.words();
+        self.grow(other.len());
+        for (word, other) in self.words_mut().iter_mut().zip(other) {
+            *word |= other;
+        }
+    }
+
+    /// The tiles not in `other`.
+    pub fn difference(&self, other: &Domain) -> Domain {
+        let mut difference = self.clone();
+        let other = other.words();
+        for (i, word) in difference.words_mut().iter_mut().enumerate() {
+            *word &= !other.get(i).copied().unwrap_or(0);
+        }
+        difference
+    }
+
+    pub fn is_subset(&self, other: &Domain) -> bool {
+        let other = other.words();
+        self.words()
+            .iter()
+            .enumerate()
+            .all(|(i, word)| word & !other.get(i).copied().unwrap_or(0) == 0)
+    }
+
+    pub fn retain(&mut self, mut keep: impl FnMut(&Tile) -> bool) {
+        for tile in self.iter().collect::<Vec<_>>() {
+            if !keep(&tile) {
+                self.remove(&tile);
+            }
+        }
+    }
+
+    pub fn to_set(&self) -> HashSet<Tile> {
+        self.iter().collect()
+    }
+}
+
+fn position(tile: &Tile) -> (usize, u64) {
+    (tile.id() / WORD_BITS, 1 << (tile.id() % WORD_BITS))
+}
+
+/// The tiles of a `Domain`, in increasing order.
+pub struct Iter<'a> {
+    words: &'a [u64],
+    index: usize,
+    current: u64,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = Tile;
+
+    fn next(&mut self) -> Option<Tile> {
+        while self.current == 0 {
+            self.index += 1;
+            self.current = *self.words.get(self.index)?;
+        }
+        let bit = self.current.trailing_zeros() as usize;
+        self.current &= self.current - 1;
+        Some(Tile(self.index * WORD_BITS + bit))
+    }
+}
+
+impl<'a> IntoIterator for &'a Domain {
+    type Item = Tile;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl FromIterator<Tile> for Domain {
+    fn from_iter<I: IntoIterator<Item = Tile>>(iter: I) -> Self {
+        let mut domain = Domain::new();
+        domain.extend(iter);
+        domain
+    }
+}
+
+impl Extend<Tile> for Domain {
+    fn extend<I: IntoIterator<Item = Tile>>(&mut self, iter: I) {
+        for tile in iter {
+            self.insert(tile);
+        }
+    }
+}
+
+impl From<HashSet<Tile>> for Domain {
+    fn from(set: HashSet<Tile>) -> Self {
+        set.into_iter().collect()
+    }
+}
+
+impl From<&HashSet<Tile>> for Domain {
+    fn from(set: &HashSet<Tile>) -> Self {
+        set.iter().cloned().collect()
+    }
+}
+
+/// Domains are equal when they hold the same tiles, whatever their width.
+impl PartialEq for Domain {
+    fn eq(&self, other: &Domain) -> bool {
+        self.is_subset(other) && other.is_subset(self)
+    }
+}
+
+impl Eq for Domain {}
+
+impl PartialEq<HashSet<Tile>> for Domain {
+    fn eq(&self, other: &HashSet<Tile>) -> bool {
+        self.len() == other.len() && other.iter().all(|tile| self.contains(tile))
+    }
+}
+
+impl PartialEq<HashSet<Tile>> for &Domain {
+    fn eq(&self, other: &HashSet<Tile>) -> bool {
+        **self == *other
+    }
+}
+
+impl Debug for Domain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::HashSetExt;
+
+    use super::*;
+
+    #[test]
+    fn test_single_word() {
+        let mut domain = Domain::from(HashSet::from_all(vec![Tile(3), Tile(0), Tile(63)]));
+        assert!(matches!(domain, Domain::Word(_)));
+        assert_eq!(domain.len(), 3);
+        assert_eq!(
+            domain.iter().collect::<Vec<_>>(),
+            vec![Tile(0), Tile(3), Tile(63)]
+        );
+
+        assert!(domain.remove(&Tile(3)));
+        assert!(!domain.remove(&Tile(3)));
+        assert!(!domain.contains(&Tile(3)));
+        assert_eq!(domain, HashSet::from_all(vec![Tile(0), Tile(63)]));
+    }
+
+    #[test]
+    fn test_multi_word() {
+        let mut domain = Domain::from(HashSet::from_all(vec![Tile(1), Tile(130)]));
+        assert!(matches!(domain, Domain::Words(_)));
+        assert_eq!(domain.iter().collect::<Vec<_>>(), vec![Tile(1), Tile(130)]);
+
+        let small = Domain::from(HashSet::from_all(vec![Tile(1), Tile(2)]));
+        assert!(!domain.is_subset(&small));
+        domain.intersect_with(&small);
+        assert_eq!(domain, small.iter().take(1).collect::<Domain>());
+        assert_eq!(domain.single(), Some(Tile(1)));
+
+        domain.union_with(&Domain::from(HashSet::from_all(vec![Tile(200)])));
+        assert_eq!(domain.len(), 2);
+        assert!(domain.contains(&Tile(200)));
+        assert_eq!(
+            domain.difference(&small),
+            HashSet::from_all(vec![Tile(200)])
+        );
+        assert_eq!(small.difference(&domain), HashSet::from_all(vec![Tile(2)]));
+    }
+
+    #[test]
+    fn test_iter_ascending() {
+        // However the tiles come in, they come out in ascending order.
+        let tiles = [Tile(130), Tile(5), Tile(64), Tile(0), Tile(63)];
+        let domain: Domain = tiles.iter().rev().cloned().collect();
+        assert_eq!(
+            domain.iter().collect::<Vec<_>>(),
+            vec![Tile(0), Tile(5), Tile(63), Tile(64), Tile(130)]
+        );
+        assert_eq!(
+            Domain::from(HashSet::from_all(tiles.to_vec()))
+                .iter()
+                .collect::<Vec<_>>(),
+            domain.iter().collect::<Vec<_>>()
+        );
+    }
+}
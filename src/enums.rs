@@ -1,45 +1,28 @@
-use bmp::Pixel;
 use rand::Rng;
 
+const MINIFIED_TILES: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
 #[derive(Clone, Debug, PartialEq, Hash, Eq, PartialOrd, Ord)]
-pub enum Tile {
-    Red,
-    Green,
-    Blue,
-}
+pub struct Tile(pub usize);
 
 impl Tile {
-    pub fn minify(&self) -> char {
-        match self {
-            Tile::Red => 'R',
-            Tile::Green => 'G',
-            Tile::Blue => 'B',
-        }
+    pub fn id(&self) -> usize {
+        self.0
     }
-}
 
-impl From<Tile> for Pixel {
-    fn from(tile: Tile) -> Self {
-        match tile {
-            Tile::Red => Pixel { r: 255, g: 0, b: 0 },
-            Tile::Green => Pixel { r: 0, g: 255, b: 0 },
-            Tile::Blue => Pixel { r: 0, g: 0, b: 255 },
+    pub fn minify(&self) -> char {
+        match MINIFIED_TILES.get(self.0) {
+            Some(c) => *c as char,
+            None => '?',
         }
     }
 }
 
-impl From<Pixel> for Tile {
-    fn from(pixel: Pixel) -> Self {
-        if pixel.r == 255 && pixel.g == 0 && pixel.b == 0 {
-            Tile::Red
-        } else if pixel.r == 0 && pixel.g == 255 && pixel.b == 0 {
-            Tile::Green
-        } else if pixel.r == 0 && pixel.g == 0 && pixel.b == 255 {
-            Tile::Blue
-        } else {
-            panic!("Invalid pixel color: {:?}", pixel)
-        }
-    }
+#[cfg(test)]
+impl Tile {
+    pub const RED: Tile = Tile(0);
+    pub const GREEN: Tile = Tile(1);
+    pub const BLUE: Tile = Tile(2);
 }
 
 #[derive(Clone, Debug, PartialEq, Hash, Eq)]
@@ -48,25 +31,216 @@ pub enum Direction {
     Down,
     Left,
     Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+    /// The next layer of a 3D grid.
+    Above,
+    /// The previous layer of a 3D grid.
+    Below,
+    /// Along a graph edge with the given label, from its source to its target.
+    Out(usize),
+    /// Along a graph edge with the given label, from its target back to its source.
+    In(usize),
 }
 
 impl Direction {
+    /// The four sides of a cell.
+    pub fn all() -> [Direction; 4] {
+        [
+            Direction::Up,
+            Direction::Down,
+            Direction::Left,
+            Direction::Right,
+        ]
+    }
+
+    /// The four corners of a cell.
+    pub fn diagonals() -> [Direction; 4] {
+        [
+            Direction::UpLeft,
+            Direction::UpRight,
+            Direction::DownLeft,
+            Direction::DownRight,
+        ]
+    }
+
+    /// The sides, then the corners.
+    pub fn moore() -> [Direction; 8] {
+        let [up, down, left, right] = Direction::all();
+        let [up_left, up_right, down_left, down_right] = Direction::diagonals();
+        [
+            up, down, left, right, up_left, up_right, down_left, down_right,
+        ]
+    }
+
+    /// The six sides of a hexagon, see `Direction::axial`.
+    pub fn hex() -> [Direction; 6] {
+        [
+            Direction::Left,
+            Direction::Right,
+            Direction::UpLeft,
+            Direction::UpRight,
+            Direction::DownLeft,
+            Direction::DownRight,
+        ]
+    }
+
+    /// Every direction: the Moore neighbourhood, then the layers above and below.
+    pub fn every() -> [Direction; 10] {
+        let [up, down, left, right, up_left, up_right, down_left, down_right] = Direction::moore();
+        [
+            up,
+            down,
+            left,
+            right,
+            up_left,
+            up_right,
+            down_left,
+            down_right,
+            Direction::Above,
+            Direction::Below,
+        ]
+    }
+
+    /// Position of the direction in `Direction::every()`, `None` for the graph edges.
+    pub fn index(&self) -> Option<usize> {
+        let index = match self {
+            Direction::Up => 0,
+            Direction::Down => 1,
+            Direction::Left => 2,
+            Direction::Right => 3,
+            Direction::UpLeft => 4,
+            Direction::UpRight => 5,
+            Direction::DownLeft => 6,
+            Direction::DownRight => 7,
+            Direction::Above => 8,
+            Direction::Below => 9,
+            Direction::Out(_) | Direction::In(_) => return None,
+        };
+        Some(index)
+    }
+
+    /// Offset within a layer, `(0, 0)` for `Above`, `Below` and the graph edges.
     pub fn offset(&self) -> (i32, i32) {
-        match self {
+        let (dx, dy, _) = self.offset3();
+        (dx, dy)
+    }
+
+    /// Offset (`dx`, `dy`, `dz`), with `y` pointing down and `z` up. Graph edges have
+    /// no offset.
+    pub fn offset3(&self) -> (i32, i32, i32) {
+        let (dx, dy) = match self {
             Direction::Up => (0, -1),
             Direction::Down => (0, 1),
             Direction::Left => (-1, 0),
             Direction::Right => (1, 0),
+            Direction::UpLeft => (-1, -1),
+            Direction::UpRight => (1, -1),
+            Direction::DownLeft => (-1, 1),
+            Direction::DownRight => (1, 1),
+            Direction::Above => return (0, 0, 1),
+            Direction::Below => return (0, 0, -1),
+            Direction::Out(_) | Direction::In(_) => return (0, 0, 0),
+        };
+        (dx, dy, 0)
+    }
+
+    /// Offset (`dq`, `dr`) on a hex grid in axial coordinates, `r` pointing down. The
+    /// directions leaving a hexagon through its sides are the left and right ones and
+    /// the four diagonals, the others return `None`.
+    pub fn axial(&self) -> Option<(i32, i32)> {
+        match self {
+            Direction::Left => Some((-1, 0)),
+            Direction::Right => Some((1, 0)),
+            Direction::UpLeft => Some((0, -1)),
+            Direction::UpRight => Some((1, -1)),
+            Direction::DownLeft => Some((-1, 1)),
+            Direction::DownRight => Some((0, 1)),
+            _ => None,
         }
     }
+
+    /// The direction whose `offset` is `offset`.
+    pub fn from_offset(offset: (i32, i32)) -> Option<Direction> {
+        Direction::moore()
+            .into_iter()
+            .find(|direction| direction.offset() == offset)
+    }
+
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::Up => Direction::Down,
+            Direction::Down => Direction::Up,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+            Direction::UpLeft => Direction::DownRight,
+            Direction::UpRight => Direction::DownLeft,
+            Direction::DownLeft => Direction::UpRight,
+            Direction::DownRight => Direction::UpLeft,
+            Direction::Above => Direction::Below,
+            Direction::Below => Direction::Above,
+            Direction::Out(label) => Direction::In(*label),
+            Direction::In(label) => Direction::Out(*label),
+        }
+    }
+}
+
+/// Which cells count as neighbours when learning rules from a sample.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Neighbourhood {
+    /// The four cells sharing a side.
+    #[default]
+    VonNeumann,
+    /// The eight cells sharing a side or a corner, so diagonal structures are learned.
+    Moore,
+}
+
+impl Neighbourhood {
+    pub fn directions(&self) -> Vec<Direction> {
+        match self {
+            Neighbourhood::VonNeumann => Direction::all().to_vec(),
+            Neighbourhood::Moore => Direction::moore().to_vec(),
+        }
+    }
+}
+
+/// Shape of the cells of a grid, which decides who their neighbours are.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Tiling {
+    /// Square cells, up to eight neighbours.
+    #[default]
+    Square,
+    /// Hexagons with a pointy top, stored in offset rows: the odd rows are shifted half
+    /// a cell to the right, see `hex`.
+    Hex,
+}
+
+impl Tiling {
+    /// The directions leading to a neighbour within a layer.
+    pub fn directions(&self) -> Vec<Direction> {
+        match self {
+            Tiling::Square => Direction::moore().to_vec(),
+            Tiling::Hex => Direction::hex().to_vec(),
+        }
+    }
+}
+
+/// How the next cell to collapse is picked.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Heuristic {
+    /// Fewest remaining possibilities.
+    Count,
+    /// Lowest Shannon entropy of the remaining weighted possibilities.
+    #[default]
+    Entropy,
 }
 
-pub fn generate_color(x: u32, y: u32) -> Tile {
-    let res = ((x + y) * rand::thread_rng().gen::<u32>()) % 3;
-    match res {
-        0 => Tile::Red,
-        1 => Tile::Green,
-        2 => Tile::Blue,
-        _ => Tile::Red,
+pub fn generate_color(x: u32, y: u32, tile_count: usize, rng: &mut impl Rng) -> Tile {
+    if tile_count == 0 {
+        return Tile(0);
     }
+    let res = ((x + y) as usize).wrapping_mul(rng.gen::<usize>()) % tile_count;
+    Tile(res)
 }
@@ -0,0 +1,99 @@
+use std::{fmt, io};
+
+use bmp::{BmpError, Pixel};
+
+/// Everything that can go wrong while reading a sample, generating or saving a result.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The file is not a bitmap the `bmp` crate can decode.
+    Bitmap(BmpError),
+    /// A pixel of the input has a color the palette does not know.
+    InvalidColor {
+        x: u32,
+        y: u32,
+        pixel: Pixel,
+    },
+    /// There is nothing to generate from: the sample yielded no rule.
+    EmptyRules,
+    InvalidDimensions {
+        width: u32,
+        height: u32,
+        depth: u32,
+    },
+    /// The cell at `(x, y)` is outside the grid.
+    OutOfBounds {
+        x: usize,
+        y: usize,
+    },
+    /// A constraint leaves the cell at `(x, y)` without any tile the rules allow.
+    InvalidConstraint {
+        x: usize,
+        y: usize,
+    },
+    /// The rules cannot be satisfied on a grid of this size.
+    Unsatisfiable,
+    /// Every attempt ran out of its contradiction or backtracking budget.
+    BudgetExhausted {
+        attempts: usize,
+        contradictions: usize,
+    },
+    /// The state is not fully collapsed or uses a tile the renderer cannot draw.
+    Unrenderable,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Bitmap(err) => write!(f, "Invalid bitmap: {}", err),
+            Error::InvalidColor { x, y, pixel } => {
+                write!(f, "Unknown color {:?} at ({}, {})", pixel, x, y)
+            }
+            Error::EmptyRules => write!(f, "The rule set is empty"),
+            Error::InvalidDimensions {
+                width,
+                height,
+                depth,
+            } => write!(f, "Invalid dimensions {}x{}x{}", width, height, depth),
+            Error::OutOfBounds { x, y } => write!(f, "({}, {}) is outside the grid", x, y),
+            Error::InvalidConstraint { x, y } => {
+                write!(f, "The constraint on ({}, {}) allows no tile", x, y)
+            }
+            Error::Unsatisfiable => write!(f, "The rules cannot be satisfied"),
+            Error::BudgetExhausted {
+                attempts,
+                contradictions,
+            } => write!(
+                f,
+                "Gave up after {} attempts and {} contradictions",
+                attempts, contradictions
+            ),
+            Error::Unrenderable => write!(f, "The state cannot be rendered"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            Error::Bitmap(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<BmpError> for Error {
+    fn from(err: BmpError) -> Self {
+        Error::Bitmap(err)
+    }
+}
@@ -1,6 +1,10 @@
-use std::{fs, io};
+use std::fs;
 
-pub fn delete_files_in_dir(path: &str) -> io::Result<()> {
+use bmp::Image;
+
+use crate::error::Result;
+
+pub fn delete_files_in_dir(path: &str) -> Result<()> {
     if let Ok(entries) = fs::read_dir(path) {
         for entry in entries.flatten() {
             // Check if it's a file
@@ -12,3 +16,27 @@ pub fn delete_files_in_dir(path: &str) -> io::Result<()> {
     }
     Ok(())
 }
+
+pub fn read_bitmap(file_name: &str) -> Result<Image> {
+    Ok(bmp::open(file_name)?)
+}
+
+pub fn save_bitmap(img: &Image, file_name: &str) -> Result<()> {
+    Ok(img.save(file_name)?)
+}
+
+/// Reads the slices of a 3D sample, the bottom layer first.
+pub fn read_slices(file_names: &[&str]) -> Result<Vec<Image>> {
+    file_names
+        .iter()
+        .map(|file_name| read_bitmap(file_name))
+        .collect()
+}
+
+/// Saves each slice into `<prefix>_<z>.bmp`, `z` starting at 0 for the bottom layer.
+pub fn save_slices(slices: &[Image], prefix: &str) -> Result<()> {
+    for (z, slice) in slices.iter().enumerate() {
+        save_bitmap(slice, &format!("{}_{}.bmp", prefix, z))?;
+    }
+    Ok(())
+}
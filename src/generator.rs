@@ -0,0 +1,483 @@
+use std::{collections::HashSet, path::PathBuf, sync::Arc};
+
+use bmp::Image;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{
+    enums::{Heuristic, Tiling},
+    error::{Error, Result},
+    inpaint::Mask,
+    observer::{NoObserver, Observer},
+    palette::Palette,
+    restart::RestartPolicy,
+    rules::{Adjacency, Rule},
+    state::{self, Render, Solver, SolverOptions, State},
+    topology::Topology,
+    trace::DirTrace,
+    weights::Weights,
+};
+
+/// A configured solver. The rules are indexed once in `build`, so the same
+/// generator can be run any number of times.
+#[derive(Clone, Debug)]
+pub struct Generator {
+    topology: Arc<dyn Topology>,
+    rules: HashSet<Rule>,
+    adjacency: Adjacency,
+    weights: Weights,
+    options: SolverOptions,
+    trace_dir: Option<PathBuf>,
+}
+
+impl Generator {
+    pub fn builder() -> GeneratorBuilder {
+        GeneratorBuilder::default()
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        let (width, height, _) = self.topology.dimensions();
+        (width as u32, height as u32)
+    }
+
+    /// Number of layers, 1 unless the generator is 3D.
+    pub fn depth(&self) -> u32 {
+        self.topology.dimensions().2 as u32
+    }
+
+    pub fn topology(&self) -> &dyn Topology {
+        self.topology.as_ref()
+    }
+
+    fn check_single_layer(&self) -> Result<()> {
+        if self.depth() > 1 {
+            let (width, height) = self.size();
+            return Err(Error::InvalidDimensions {
+                width,
+                height,
+                depth: self.depth(),
+            });
+        }
+        Ok(())
+    }
+
+    pub fn options(&self) -> &SolverOptions {
+        &self.options
+    }
+
+    /// Runs the solver with a generator seeded from `options.seed`, or from the OS
+    /// when no seed was given.
+    pub fn generate(&self) -> Result<State> {
+        self.generate_with_rng(&mut self.rng())
+    }
+
+    /// Like `generate`, drawing every random choice from `rng` instead of the seed.
+    pub fn generate_with_rng(&self, rng: &mut impl Rng) -> Result<State> {
+        match &self.trace_dir {
+            Some(dir) => self.run(rng, &mut DirTrace::new(dir)),
+            None => self.run(rng, &mut NoObserver),
+        }
+    }
+
+    /// Like `generate`, sending every solver event to `observer` instead of dumping
+    /// into the trace directory.
+    pub fn generate_observed(&self, observer: &mut impl Observer) -> Result<State> {
+        self.run(&mut self.rng(), observer)
+    }
+
+    fn rng(&self) -> StdRng {
+        match self.options.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+
+    /// The grid every generation starts from, with the tiles that can never fit already
+    /// removed. Restrict some of its cells, e.g. with `State::restrict`, then pass it
+    /// to `generate_from`.
+    pub fn initial_state(&self) -> Result<State> {
+        state::initial_state(
+            self.topology.clone(),
+            &self.rules,
+            &self.adjacency,
+            &self.options,
+        )
+    }
+
+    /// Like `generate`, keeping the cells of `constrained` to the tiles it allows.
+    /// Fails with `Error::InvalidDimensions` when `constrained` is not the size of
+    /// the generator, with `Error::InvalidConstraint` when a cell allows none of the
+    /// tiles of the rules and with `Error::Unsatisfiable` when the constraints
+    /// contradict the rules.
+    pub fn generate_from(&self, constrained: &State) -> Result<State> {
+        let size = (constrained.width, constrained.height, constrained.depth);
+        if size != self.topology.dimensions() {
+            return Err(Error::InvalidDimensions {
+                width: size.0 as u32,
+                height: size.1 as u32,
+                depth: size.2 as u32,
+            });
+        }
+        let mut rng = self.rng();
+        match &self.trace_dir {
+            Some(dir) => self.run_from(constrained, &mut rng, &mut DirTrace::new(dir)),
+            None => self.run_from(constrained, &mut rng, &mut NoObserver),
+        }
+    }
+
+    fn run(&self, rng: &mut impl Rng, observer: &mut impl Observer) -> Result<State> {
+        self.solver_with(rng, observer)?.finish()
+    }
+
+    fn run_from(
+        &self,
+        constrained: &State,
+        rng: &mut impl Rng,
+        observer: &mut impl Observer,
+    ) -> Result<State> {
+        Solver::from_state(
+            self.topology.clone(),
+            constrained,
+            &self.rules,
+            &self.adjacency,
+            &self.weights,
+            &self.options,
+            rng,
+            observer,
+        )?
+        .finish()
+    }
+
+    /// A solver to drive step by step, seeded like `generate` and without tracing.
+    pub fn solver(&self) -> Result<Solver<'_, StdRng, NoObserver>> {
+        self.solver_with(self.rng(), NoObserver)
+    }
+
+    /// A solver to drive step by step, drawing from `rng` and reporting to `observer`.
+    pub fn solver_with<R: Rng, O: Observer>(
+        &self,
+        rng: R,
+        observer: O,
+    ) -> Result<Solver<'_, R, O>> {
+        Solver::new(
+            self.topology.clone(),
+            &self.rules,
+            &self.adjacency,
+            &self.weights,
+            &self.options,
+            rng,
+            observer,
+        )
+    }
+
+    /// Generates a state and turns it into an image with `renderer`, e.g. a
+    /// `Palette`, an `OverlappingModel` or a `Tileset`. Fails with
+    /// `Error::InvalidDimensions` without generating anything when the generator is
+    /// 3D, see `voxel::render_slices`.
+    pub fn generate_image(&self, renderer: &impl Render) -> Result<Image> {
+        self.check_single_layer()?;
+        let state = self.generate()?;
+        renderer.render(&state)
+    }
+
+    /// Like `generate_image`, starting from the cells of `constrained`.
+    pub fn generate_image_from(
+        &self,
+        constrained: &State,
+        renderer: &impl Render,
+    ) -> Result<Image> {
+        self.check_single_layer()?;
+        let state = self.generate_from(constrained)?;
+        renderer.render(&state)
+    }
+
+    /// Regenerates the cells of `img` covered by `mask` and keeps the others, reading
+    /// and drawing the tiles with `palette`. Fails with `Error::InvalidDimensions`
+    /// when `img` or `mask` is not the size of the generator.
+    pub fn inpaint(&self, img: &Image, mask: &Mask, palette: &Palette) -> Result<Image> {
+        let constrained = mask.constrain(img, palette)?;
+        self.generate_image_from(&constrained, palette)
+    }
+}
+
+/// Collects the configuration of a `Generator`. Everything but the size and the
+/// rules has a default: uniform weights, `SolverOptions::default()` and no tracing.
+#[derive(Clone, Debug, Default)]
+pub struct GeneratorBuilder {
+    width: u32,
+    height: u32,
+    depth: Option<u32>,
+    topology: Option<Arc<dyn Topology>>,
+    rules: HashSet<Rule>,
+    weights: Weights,
+    options: SolverOptions,
+    trace_dir: Option<PathBuf>,
+}
+
+impl GeneratorBuilder {
+    pub fn size(mut self, width: u32, height: u32) -> Self {
+        self.width = width;
+        self.height = height;
+        self
+    }
+
+    /// Generates `depth` layers of the size instead of a single one. The rules then
+    /// need `Direction::Above` and `Direction::Below` to tie the layers together.
+    pub fn depth(mut self, depth: u32) -> Self {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Lays the cells out along `topology`, e.g. a `Graph`, instead of a grid. The
+    /// size, depth, periodic and tiling settings are then ignored.
+    pub fn topology(mut self, topology: impl Topology + 'static) -> Self {
+        self.topology = Some(Arc::new(topology));
+        self
+    }
+
+    pub fn rules(mut self, rules: HashSet<Rule>) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn weights(mut self, weights: Weights) -> Self {
+        self.weights = weights;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.options.seed = Some(seed);
+        self
+    }
+
+    pub fn heuristic(mut self, heuristic: Heuristic) -> Self {
+        self.options.heuristic = heuristic;
+        self
+    }
+
+    pub fn max_backtrack_depth(mut self, depth: usize) -> Self {
+        self.options.max_backtrack_depth = Some(depth);
+        self
+    }
+
+    /// Makes the opposite edges of each layer neighbours, see `SolverOptions::periodic`.
+    pub fn periodic(mut self, periodic: bool) -> Self {
+        self.options.periodic = periodic;
+        self
+    }
+
+    /// Shape of the cells, see `Tiling`. Square by default.
+    pub fn tiling(mut self, tiling: Tiling) -> Self {
+        self.options.tiling = tiling;
+        self
+    }
+
+    pub fn restart(mut self, restart: RestartPolicy) -> Self {
+        self.options.restart = restart;
+        self
+    }
+
+    /// Dumps the rules and the intermediate states into `dir` on every run. The
+    /// directory is created when missing, its existing files are left alone.
+    pub fn trace_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.trace_dir = Some(dir.into());
+        self
+    }
+
+    /// Replaces every solver option at once.
+    pub fn options(mut self, options: SolverOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Fails with `Error::InvalidDimensions` when the size is zero and with
+    /// `Error::EmptyRules` when there are no rules.
+    pub fn build(self) -> Result<Generator> {
+        let topology = match self.topology {
+            Some(topology) => topology,
+            None => {
+                let (width, height) = (self.width as usize, self.height as usize);
+                let depth = self.depth.unwrap_or(1) as usize;
+                Arc::new(self.options.grid(width, height, depth))
+            }
+        };
+        let (width, height, depth) = topology.dimensions();
+        // The rows of a periodic hex grid only line up across the edge in pairs.
+        let odd_hex_rows = topology.grid().is_some_and(|grid| {
+            grid.periodic && grid.tiling == Tiling::Hex && grid.height % 2 == 1
+        });
+        if width == 0 || height == 0 || depth == 0 || odd_hex_rows {
+            return Err(Error::InvalidDimensions {
+                width: width as u32,
+                height: height as u32,
+                depth: depth as u32,
+            });
+        }
+        if self.rules.is_empty() {
+            return Err(Error::EmptyRules);
+        }
+        Ok(Generator {
+            topology,
+            adjacency: Adjacency::new(&self.rules),
+            rules: self.rules,
+            weights: self.weights,
+            options: self.options,
+            trace_dir: self.trace_dir,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLUE, LIME, RED};
+    use rstest::{fixture, rstest};
+
+    use crate::{domain::Domain, enums::Tile, state::HashSetExt, trace::MemoryTrace};
+
+    use super::*;
+
+    #[fixture]
+    fn rules_checkerboard() -> HashSet<Rule> {
+        Rule::checkerboard()
+    }
+
+    #[rstest]
+    fn test_build_invalid(rules_checkerboard: HashSet<Rule>) {
+        assert!(matches!(
+            Generator::builder().size(4, 4).build(),
+            Err(Error::EmptyRules)
+        ));
+        assert!(matches!(
+            Generator::builder()
+                .size(0, 4)
+                .rules(rules_checkerboard.clone())
+                .build(),
+            Err(Error::InvalidDimensions {
+                width: 0,
+                height: 4,
+                depth: 1
+            })
+        ));
+
+        let hex = Generator::builder()
+            .size(4, 3)
+            .rules(rules_checkerboard)
+            .tiling(Tiling::Hex);
+        assert!(hex.clone().build().is_ok());
+        assert!(matches!(
+            hex.clone().periodic(true).build(),
+            Err(Error::InvalidDimensions {
+                width: 4,
+                height: 3,
+                depth: 1
+            })
+        ));
+        assert!(hex.size(4, 4).periodic(true).build().is_ok());
+    }
+
+    #[rstest]
+    fn test_same_seed_same_state(rules_checkerboard: HashSet<Rule>) {
+        let generator = Generator::builder()
+            .size(6, 5)
+            .rules(rules_checkerboard)
+            .seed(7)
+            .build()
+            .unwrap();
+
+        let first = generator.generate().unwrap();
+        let second = generator.generate().unwrap();
+        assert_eq!(first.possible_vals.cells(), second.possible_vals.cells());
+    }
+
+    #[rstest]
+    fn test_generate_observed(rules_checkerboard: HashSet<Rule>) {
+        let generator = Generator::builder()
+            .size(2, 2)
+            .rules(rules_checkerboard)
+            .seed(1)
+            .build()
+            .unwrap();
+
+        let mut trace = MemoryTrace::default();
+        let state = generator.generate_observed(&mut trace).unwrap();
+        assert_eq!(trace.rules.lines().count(), 8);
+        assert_eq!(trace.states[0].0, "attempt_0_initial");
+        assert_eq!(
+            state.possible_vals.cells(),
+            generator.generate().unwrap().possible_vals.cells()
+        );
+    }
+
+    #[rstest]
+    fn test_generate_image(rules_checkerboard: HashSet<Rule>) {
+        let mut palette = Palette::new();
+        palette.insert(RED);
+        palette.insert(LIME);
+        palette.insert(BLUE);
+
+        let generator = Generator::builder()
+            .size(3, 2)
+            .rules(rules_checkerboard)
+            .build()
+            .unwrap();
+
+        let img = generator.generate_image(&palette).unwrap();
+        assert_eq!(img.get_width(), 3);
+        assert_eq!(img.get_height(), 2);
+        assert_ne!(img.get_pixel(0, 0), img.get_pixel(1, 0));
+        assert_ne!(img.get_pixel(0, 0), img.get_pixel(0, 1));
+    }
+
+    #[rstest]
+    fn test_periodic(rules_checkerboard: HashSet<Rule>) {
+        let builder = Generator::builder()
+            .size(3, 2)
+            .rules(rules_checkerboard)
+            .periodic(true);
+        // An odd width cannot wrap a checkerboard around.
+        assert!(builder.clone().build().unwrap().generate().is_err());
+
+        let state = builder.size(4, 2).build().unwrap().generate().unwrap();
+        assert_ne!(state.get(0, 0), state.get(3, 0));
+        assert_ne!(state.get(0, 0), state.get(0, 1));
+    }
+
+    #[rstest]
+    fn test_generate_from(rules_checkerboard: HashSet<Rule>) {
+        let generator = Generator::builder()
+            .size(4, 3)
+            .rules(rules_checkerboard)
+            .build()
+            .unwrap();
+
+        let mut constrained = generator.initial_state().unwrap();
+        constrained
+            .restrict(1, 0, Domain::from_iter([Tile::BLUE]))
+            .unwrap();
+        let state = generator.generate_from(&constrained).unwrap();
+        assert_eq!(state.get(1, 0), HashSet::from_all(vec![Tile::BLUE]));
+        assert_eq!(state.get(0, 0), HashSet::from_all(vec![Tile::RED]));
+        assert_eq!(state.get(3, 1), HashSet::from_all(vec![Tile::RED]));
+
+        // Two neighbours cannot both be blue on a checkerboard.
+        constrained
+            .restrict(2, 0, Domain::from_iter([Tile::BLUE]))
+            .unwrap();
+        assert!(matches!(
+            generator.generate_from(&constrained),
+            Err(Error::Unsatisfiable)
+        ));
+
+        let mut green = State::new(4, 3, &HashSet::from_all(vec![Tile::GREEN]));
+        green
+            .restrict(0, 0, Domain::from_iter([Tile::GREEN]))
+            .unwrap();
+        assert!(matches!(
+            generator.generate_from(&green),
+            Err(Error::InvalidConstraint { x: 0, y: 0 })
+        ));
+        assert!(matches!(
+            generator.generate_from(&State::new(2, 2, &HashSet::new())),
+            Err(Error::InvalidDimensions { .. })
+        ));
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::HashSet;
+
+use bmp::{Image, Pixel};
+
+use crate::{
+    enums::Direction,
+    error::{Error, Result},
+    palette::Palette,
+    rules::Rule,
+};
+
+/// Axial coordinates (`q`, `r`) of the cell in column `x` of row `y`.
+pub fn to_axial((x, y): (i64, i64)) -> (i64, i64) {
+    (x - y.div_euclid(2), y)
+}
+
+/// Column and row of the cell at axial coordinates (`q`, `r`).
+pub fn from_axial((q, r): (i64, i64)) -> (i64, i64) {
+    (q + r.div_euclid(2), r)
+}
+
+/// Column and row of the cell next to (`x`, `y`) in `direction`, possibly outside the
+/// grid. `None` for a direction that is not one of `Direction::hex()`.
+pub fn step((x, y): (i64, i64), direction: &Direction) -> Option<(i64, i64)> {
+    let (dq, dr) = direction.axial()?;
+    let (q, r) = to_axial((x, y));
+    Some(from_axial((q + dq as i64, r + dr as i64)))
+}
+
+/// Where `direction` points once mirrored left to right when `mirrored`, then turned
+/// `turns` sixths of a turn clockwise. Directions that are not one of
+/// `Direction::hex()` are left alone, like `Transform::direction` leaves the layers.
+pub fn transform_direction(direction: &Direction, turns: u8, mirrored: bool) -> Direction {
+    let Some((mut dq, mut dr)) = direction.axial() else {
+        return direction.clone();
+    };
+    if mirrored {
+        (dq, dr) = (-dq - dr, dr);
+    }
+    for _ in 0..turns % 6 {
+        (dq, dr) = (-dr, dq + dr);
+    }
+    Direction::hex()
+        .into_iter()
+        .find(|candidate| candidate.axial() == Some((dq, dr)))
+        .expect("Transforms map hex directions onto hex directions")
+}
+
+/// Like `rules::extract_rules`, reading `img` as a hex sample: pixel (`x`, `y`) is
+/// column `x` of row `y`, the odd rows shifted half a cell to the right. `Render`
+/// draws a hex state back in the same layout.
+pub fn extract_rules(img: &Image, palette: &Palette) -> Result<HashSet<Rule>> {
+    for (x, y) in img.coordinates() {
+        let pixel = img.get_pixel(x, y);
+        if palette.tile(pixel).is_none() {
+            return Err(Error::InvalidColor { x, y, pixel });
+        }
+    }
+    let to_tile = |pixel: Pixel| palette.tile(pixel).expect("Every color was checked above");
+    let (w, h) = (img.get_width() as i64, img.get_height() as i64);
+    let mut rules: HashSet<Rule> = HashSet::new();
+    for (x, y) in img.coordinates() {
+        let curr_tile = to_tile(img.get_pixel(x, y));
+        for direction in Direction::hex() {
+            let Some((nx, ny)) = step((x as i64, y as i64), &direction) else {
+                continue;
+            };
+            if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                continue;
+            }
+            let adj_tile = to_tile(img.get_pixel(nx as u32, ny as u32));
+            rules.insert(Rule::new(adj_tile, curr_tile.clone(), direction));
+        }
+    }
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLUE, LIME, RED};
+
+    use crate::{enums::Tiling, generator::Generator, state::Render};
+
+    use super::*;
+
+    /// Three colors, no two neighbours alike.
+    fn three_colored(w: u32, h: u32) -> Image {
+        let mut img = Image::new(w, h);
+        for (x, y) in img.coordinates() {
+            let (q, r) = to_axial((x as i64, y as i64));
+            img.set_pixel(x, y, [RED, LIME, BLUE][(q - r).rem_euclid(3) as usize]);
+        }
+        img
+    }
+
+    #[test]
+    fn test_step() {
+        // An even row, then an odd one shifted to the right.
+        assert_eq!(step((1, 2), &Direction::UpLeft), Some((0, 1)));
+        assert_eq!(step((1, 2), &Direction::DownRight), Some((1, 3)));
+        assert_eq!(step((1, 1), &Direction::UpLeft), Some((1, 0)));
+        assert_eq!(step((1, 1), &Direction::DownRight), Some((2, 2)));
+        assert_eq!(step((1, 1), &Direction::Left), Some((0, 1)));
+        assert_eq!(step((1, 1), &Direction::Up), None);
+
+        for direction in Direction::hex() {
+            let there = step((3, 5), &direction).unwrap();
+            assert_eq!(step(there, &direction.opposite()), Some((3, 5)));
+        }
+        assert_eq!(from_axial(to_axial((4, -3))), (4, -3));
+    }
+
+    #[test]
+    fn test_transform_direction() {
+        // Clockwise from the right, one sixth of a turn at a time.
+        let clockwise = [
+            Direction::Right,
+            Direction::DownRight,
+            Direction::DownLeft,
+            Direction::Left,
+            Direction::UpLeft,
+            Direction::UpRight,
+        ];
+        for (i, direction) in clockwise.iter().enumerate() {
+            assert_eq!(
+                transform_direction(direction, 1, false),
+                clockwise[(i + 1) % 6]
+            );
+            assert_eq!(transform_direction(direction, 6, false), *direction);
+        }
+        assert_eq!(
+            transform_direction(&Direction::UpLeft, 0, true),
+            Direction::UpRight
+        );
+        assert_eq!(
+            transform_direction(&Direction::Right, 0, true),
+            Direction::Left
+        );
+        assert_eq!(
+            transform_direction(&Direction::Above, 2, true),
+            Direction::Above
+        );
+
+        for turns in 0..6 {
+            for mirrored in [false, true] {
+                for direction in Direction::hex() {
+                    assert_eq!(
+                        transform_direction(&direction.opposite(), turns, mirrored),
+                        transform_direction(&direction, turns, mirrored).opposite()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_generate_hex() {
+        let sample = three_colored(6, 6);
+        let palette = Palette::from_image(&sample);
+        let rules = extract_rules(&sample, &palette).unwrap();
+        // Each color has a single neighbour color in each direction.
+        assert_eq!(rules.len(), 18);
+
+        let generator = Generator::builder()
+            .size(5, 4)
+            .rules(rules.clone())
+            .tiling(Tiling::Hex)
+            .seed(3)
+            .build()
+            .unwrap();
+        let state = generator.generate().unwrap();
+        for (x, y) in state.possible_vals.coords() {
+            for direction in Direction::hex() {
+                if let Some((nx, ny)) = state.possible_vals.neighbour(x, y, &direction) {
+                    assert_ne!(state.get(x, y), state.get(nx, ny));
+                }
+            }
+        }
+        let img = palette.render(&state).unwrap();
+        assert!(extract_rules(&img, &palette).unwrap().is_subset(&rules));
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+
+use bmp::{consts::BLACK, Image};
+
+use crate::{
+    domain::Domain,
+    error::{Error, Result},
+    palette::Palette,
+    state::State,
+};
+
+/// The cells of an image to regenerate, the others are kept as they are.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mask {
+    width: u32,
+    height: u32,
+    masked: Vec<Vec<bool>>,
+}
+
+impl Mask {
+    /// A mask of the given size where nothing is regenerated yet.
+    pub fn new(width: u32, height: u32) -> Self {
+        Mask {
+            width,
+            height,
+            masked: vec![vec![false; height as usize]; width as usize],
+        }
+    }
+
+    /// Every pixel of `img` that is not black is regenerated.
+    pub fn from_image(img: &Image) -> Self {
+        let mut mask = Mask::new(img.get_width(), img.get_height());
+        for (x, y) in img.coordinates() {
+            mask.masked[x as usize][y as usize] = img.get_pixel(x, y) != BLACK;
+        }
+        mask
+    }
+
+    /// Each rectangle is `(x, y, width, height)`, see `insert_rect`.
+    pub fn from_rects(width: u32, height: u32, rects: &[(u32, u32, u32, u32)]) -> Self {
+        let mut mask = Mask::new(width, height);
+        for &(x, y, w, h) in rects {
+            mask.insert_rect(x, y, w, h);
+        }
+        mask
+    }
+
+    /// Regenerates the `w` by `h` rectangle whose top-left corner is `(x, y)`. The
+    /// part outside the mask is ignored.
+    pub fn insert_rect(&mut self, x: u32, y: u32, w: u32, h: u32) {
+        for cx in x..x.saturating_add(w).min(self.width) {
+            for cy in y..y.saturating_add(h).min(self.height) {
+                self.masked[cx as usize][cy as usize] = true;
+            }
+        }
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn is_masked(&self, x: u32, y: u32) -> bool {
+        self.masked[x as usize][y as usize]
+    }
+
+    /// The grid of `img` with every cell outside the mask restricted to the tile of its
+    /// pixel, ready for `Generator::generate_from`. Fails with
+    /// `Error::InvalidDimensions` when `img` is not the size of the mask and with
+    /// `Error::InvalidColor` when a kept pixel is not in `palette`.
+    pub fn constrain(&self, img: &Image, palette: &Palette) -> Result<State> {
+        if (img.get_width(), img.get_height()) != self.size() {
+            return Err(Error::InvalidDimensions {
+                width: img.get_width(),
+                height: img.get_height(),
+                depth: 1,
+            });
+        }
+        let mut state = State::new(
+            self.width as usize,
+            self.height as usize,
+            &palette.tiles().collect::<HashSet<_>>(),
+        );
+        for (x, y) in img.coordinates() {
+            if self.is_masked(x, y) {
+                continue;
+            }
+            let pixel = img.get_pixel(x, y);
+            let tile = palette
+                .tile(pixel)
+                .ok_or(Error::InvalidColor { x, y, pixel })?;
+            state.restrict(x as usize, y as usize, Domain::from_iter([tile]))?;
+        }
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLUE, RED, WHITE};
+
+    use crate::{generator::Generator, rules::extract_rules};
+
+    use super::*;
+
+    fn checkerboard(w: u32, h: u32) -> Image {
+        let mut img = Image::new(w, h);
+        for (x, y) in img.coordinates() {
+            img.set_pixel(x, y, if (x + y) % 2 == 0 { RED } else { BLUE });
+        }
+        img
+    }
+
+    #[test]
+    fn test_from_image() {
+        let mut img = Image::new(3, 2);
+        img.set_pixel(1, 0, WHITE);
+        img.set_pixel(2, 1, RED);
+
+        let mask = Mask::from_image(&img);
+        assert_eq!(mask, Mask::from_rects(3, 2, &[(1, 0, 1, 1), (2, 1, 5, 5)]));
+        assert!(!mask.is_masked(0, 0));
+    }
+
+    #[test]
+    fn test_inpaint() {
+        let img = checkerboard(5, 4);
+        let palette = Palette::from_image(&img);
+        let generator = Generator::builder()
+            .size(5, 4)
+            .rules(extract_rules(&img, &palette).unwrap())
+            .seed(2)
+            .build()
+            .unwrap();
+
+        // Scribble over a patch, then let the generator repair it.
+        let mut damaged = img.clone();
+        damaged.set_pixel(1, 1, RED);
+        damaged.set_pixel(2, 1, WHITE);
+        let mask = Mask::from_rects(5, 4, &[(1, 1, 2, 2)]);
+        let repaired = generator.inpaint(&damaged, &mask, &palette).unwrap();
+        assert_eq!(repaired, img);
+
+        // The white pixel is outside the mask this time.
+        let mask = Mask::from_rects(5, 4, &[(1, 1, 1, 1)]);
+        assert!(matches!(
+            generator.inpaint(&damaged, &mask, &palette),
+            Err(Error::InvalidColor { x: 2, y: 1, .. })
+        ));
+    }
+}
@@ -1,4 +1,20 @@
+pub mod domain;
 pub mod enums;
+pub mod error;
 pub mod files;
+pub mod generator;
+pub mod hex;
+pub mod inpaint;
+pub mod observer;
+pub mod overlapping;
+pub mod palette;
+pub mod ranking;
+pub mod restart;
 pub mod rules;
 pub mod state;
+pub mod symmetry;
+pub mod tiled;
+pub mod topology;
+pub mod trace;
+pub mod voxel;
+pub mod weights;
@@ -1,34 +1,54 @@
 use bmp::Image;
 use wfc::enums::generate_color;
+use wfc::error::Result;
+use wfc::files::{delete_files_in_dir, read_bitmap, save_bitmap};
+use wfc::generator::Generator;
+use wfc::palette::Palette;
+use wfc::restart::{RestartPolicy, RestartSchedule};
 use wfc::rules::extract_rules;
-use wfc::state::generate_image;
+use wfc::weights::extract_weights;
 
-pub fn generate_bitmap(w: u32, h: u32) -> Image {
+pub fn generate_bitmap(w: u32, h: u32, palette: &Palette) -> Image {
+    let mut rng = rand::thread_rng();
     let mut img = Image::new(w, h);
     for (x, y) in img.coordinates() {
-        img.set_pixel(x, y, generate_color(x, y).into());
+        let tile = generate_color(x, y, palette.len(), &mut rng);
+        if let Some(pixel) = palette.pixel(&tile) {
+            img.set_pixel(x, y, pixel);
+        }
     }
     img
 }
 
-pub fn save_bitmap(img: Image, file_name: &str) {
-    let _ = img.save(file_name);
-}
-
-pub fn read_bitmap(file_name: &str) -> Image {
-    let img = bmp::open(file_name).unwrap();
-    println!("read_bitmap: {:?}", img);
-    img
+fn run() -> Result<()> {
+    let file_name = "imgs/noel.bmp";
+    let final_file_name = "imgs/noel_final.bmp";
+    let trace_dir = "imgs/output";
+    let input_img = read_bitmap(file_name)?;
+    println!("read_bitmap: {:?}", input_img);
+    let palette = Palette::from_image(&input_img);
+    let rules = extract_rules(&input_img, &palette)?;
+    let weights = extract_weights(&input_img, &palette)?;
+    delete_files_in_dir(trace_dir)?;
+    let generator = Generator::builder()
+        .size(16, 16)
+        .rules(rules)
+        .weights(weights)
+        .restart(RestartPolicy {
+            max_attempts: 5,
+            max_contradictions: Some(100),
+            schedule: RestartSchedule::Luby,
+        })
+        .trace_dir(trace_dir)
+        .build()?;
+    let img = generator.generate_image(&palette)?;
+    println!("Generated image: {:?}", img);
+    save_bitmap(&img, final_file_name)
 }
 
 fn main() {
-    let file_name = "imgs/noel.bmp";
-    let final_file_name = "imgs/noel_final.bmp";
-    let input_img = read_bitmap(file_name);
-    let rules = extract_rules(&input_img);
-    let res_img = generate_image(16, 16, &rules);
-    println!("Generated image: {:?}", res_img);
-    if let Some(img) = res_img {
-        save_bitmap(img, final_file_name);
+    if let Err(err) = run() {
+        eprintln!("Generation failed: {}", err);
+        std::process::exit(1);
     }
 }
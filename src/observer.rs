@@ -0,0 +1,227 @@
+use std::collections::HashSet;
+
+use crate::{
+    domain::Domain,
+    enums::Tile,
+    error::{Error, Result},
+    rules::Rule,
+    state::State,
+};
+
+/// Hooks into every step of the solver. All callbacks do nothing by default, so an
+/// observer only implements the events it cares about. An error returned by a
+/// callback stops the generation and is returned by the solver.
+pub trait Observer {
+    /// Called once, with the rules and the grid after the initial propagation.
+    fn on_start(&mut self, _rules: &HashSet<Rule>, _state: &State) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before every attempt, the first one included, with a fresh grid.
+    fn on_restart(&mut self, _attempt: usize, _state: &State) -> Result<()> {
+        Ok(())
+    }
+
+    /// The heuristic picked the cell at `coord` to collapse next.
+    fn on_cell_selected(&mut self, _coord: (usize, usize), _domain: &Domain) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_collapse(&mut self, _coord: (usize, usize), _tile: &Tile) -> Result<()> {
+        Ok(())
+    }
+
+    /// Propagation removed possibilities from the cell at `coord`.
+    fn on_domain_reduced(
+        &mut self,
+        _coord: (usize, usize),
+        _before: &Domain,
+        _after: &Domain,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    /// Collapsing the cell at `coord` left another cell without any possibility.
+    fn on_contradiction(&mut self, _coord: (usize, usize), _state: &State) -> Result<()> {
+        Ok(())
+    }
+
+    /// The solver unwound its decisions up to a consistent grid, undoing the choice of
+    /// `tile` for the cell at `coord` and banning it there.
+    fn on_backtrack(&mut self, _coord: (usize, usize), _tile: &Tile, _state: &State) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_finished(&mut self, _result: std::result::Result<&State, &Error>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Ignores every event.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoObserver;
+
+impl Observer for NoObserver {}
+
+/// Forwards every event to both observers, the first one first.
+impl<A: Observer, B: Observer> Observer for (A, B) {
+    fn on_start(&mut self, rules: &HashSet<Rule>, state: &State) -> Result<()> {
+        self.0.on_start(rules, state)?;
+        self.1.on_start(rules, state)
+    }
+
+    fn on_restart(&mut self, attempt: usize, state: &State) -> Result<()> {
+        self.0.on_restart(attempt, state)?;
+        self.1.on_restart(attempt, state)
+    }
+
+    fn on_cell_selected(&mut self, coord: (usize, usize), domain: &Domain) -> Result<()> {
+        self.0.on_cell_selected(coord, domain)?;
+        self.1.on_cell_selected(coord, domain)
+    }
+
+    fn on_collapse(&mut self, coord: (usize, usize), tile: &Tile) -> Result<()> {
+        self.0.on_collapse(coord, tile)?;
+        self.1.on_collapse(coord, tile)
+    }
+
+    fn on_domain_reduced(
+        &mut self,
+        coord: (usize, usize),
+        before: &Domain,
+        after: &Domain,
+    ) -> Result<()> {
+        self.0.on_domain_reduced(coord, before, after)?;
+        self.1.on_domain_reduced(coord, before, after)
+    }
+
+    fn on_contradiction(&mut self, coord: (usize, usize), state: &State) -> Result<()> {
+        self.0.on_contradiction(coord, state)?;
+        self.1.on_contradiction(coord, state)
+    }
+
+    fn on_backtrack(&mut self, coord: (usize, usize), tile: &Tile, state: &State) -> Result<()> {
+        self.0.on_backtrack(coord, tile, state)?;
+        self.1.on_backtrack(coord, tile, state)
+    }
+
+    fn on_finished(&mut self, result: std::result::Result<&State, &Error>) -> Result<()> {
+        self.0.on_finished(result)?;
+        self.1.on_finished(result)
+    }
+}
+
+/// Lets a caller keep ownership of its observer while the solver borrows it.
+impl<O: Observer + ?Sized> Observer for &mut O {
+    fn on_start(&mut self, rules: &HashSet<Rule>, state: &State) -> Result<()> {
+        (**self).on_start(rules, state)
+    }
+
+    fn on_restart(&mut self, attempt: usize, state: &State) -> Result<()> {
+        (**self).on_restart(attempt, state)
+    }
+
+    fn on_cell_selected(&mut self, coord: (usize, usize), domain: &Domain) -> Result<()> {
+        (**self).on_cell_selected(coord, domain)
+    }
+
+    fn on_collapse(&mut self, coord: (usize, usize), tile: &Tile) -> Result<()> {
+        (**self).on_collapse(coord, tile)
+    }
+
+    fn on_domain_reduced(
+        &mut self,
+        coord: (usize, usize),
+        before: &Domain,
+        after: &Domain,
+    ) -> Result<()> {
+        (**self).on_domain_reduced(coord, before, after)
+    }
+
+    fn on_contradiction(&mut self, coord: (usize, usize), state: &State) -> Result<()> {
+        (**self).on_contradiction(coord, state)
+    }
+
+    fn on_backtrack(&mut self, coord: (usize, usize), tile: &Tile, state: &State) -> Result<()> {
+        (**self).on_backtrack(coord, tile, state)
+    }
+
+    fn on_finished(&mut self, result: std::result::Result<&State, &Error>) -> Result<()> {
+        (**self).on_finished(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generator::Generator;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct Counter {
+        restarts: usize,
+        selected: usize,
+        collapsed: usize,
+        reduced: Vec<(usize, usize)>,
+        finished: Option<bool>,
+    }
+
+    impl Observer for Counter {
+        fn on_restart(&mut self, _attempt: usize, _state: &State) -> Result<()> {
+            self.restarts += 1;
+            Ok(())
+        }
+
+        fn on_cell_selected(&mut self, _coord: (usize, usize), domain: &Domain) -> Result<()> {
+            assert_eq!(domain.len(), 2);
+            self.selected += 1;
+            Ok(())
+        }
+
+        fn on_collapse(&mut self, _coord: (usize, usize), _tile: &Tile) -> Result<()> {
+            self.collapsed += 1;
+            Ok(())
+        }
+
+        fn on_domain_reduced(
+            &mut self,
+            coord: (usize, usize),
+            before: &Domain,
+            after: &Domain,
+        ) -> Result<()> {
+            assert_eq!((before.len(), after.len()), (2, 1));
+            self.reduced.push(coord);
+            Ok(())
+        }
+
+        fn on_finished(&mut self, result: std::result::Result<&State, &Error>) -> Result<()> {
+            self.finished = Some(result.is_ok());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_events() {
+        let generator = Generator::builder()
+            .size(3, 3)
+            .rules(Rule::checkerboard())
+            .seed(3)
+            .build()
+            .unwrap();
+
+        let mut observers = (NoObserver, Counter::default());
+        generator.generate_observed(&mut observers).unwrap();
+        let counter = observers.1;
+
+        // On a checkerboard the first collapse decides every other cell.
+        assert_eq!(counter.restarts, 1);
+        assert_eq!(counter.selected, 1);
+        assert_eq!(counter.collapsed, 1);
+        assert_eq!(counter.reduced.len(), 8);
+        assert_eq!(
+            counter.reduced.iter().collect::<HashSet<_>>().len(),
+            counter.reduced.len()
+        );
+        assert_eq!(counter.finished, Some(true));
+    }
+}
@@ -0,0 +1,244 @@
+use std::collections::{HashMap, HashSet};
+
+use bmp::Image;
+
+use crate::{
+    enums::{Direction, Tile},
+    error::{Error, Result},
+    palette::Palette,
+    rules::Rule,
+    state::{Render, State},
+    symmetry::{Symmetry, Transform},
+    weights::Weights,
+};
+
+pub const MIN_PATTERN_SIZE: usize = 2;
+pub const MAX_PATTERN_SIZE: usize = 5;
+
+/// The overlapping model: every unique `n`×`n` window of the sample becomes a
+/// tile, and two patterns may be neighbours when their overlapping pixels agree.
+#[derive(Clone, Debug)]
+pub struct OverlappingModel {
+    pub n: usize,
+    pub palette: Palette,
+    /// Colors of each pattern, stored row by row. Pattern `i` is `Tile(i)`.
+    pub patterns: Vec<Vec<Tile>>,
+    /// How many times each pattern occurs in the sample.
+    pub weights: Weights,
+}
+
+impl OverlappingModel {
+    /// Slides an `n`×`n` window over `img`. Fails with `Error::InvalidDimensions` when
+    /// `n` is outside `MIN_PATTERN_SIZE..=MAX_PATTERN_SIZE` or larger than the image.
+    pub fn new(img: &Image, n: usize) -> Result<Self> {
+        OverlappingModel::with_symmetry(img, n, Symmetry::None)
+    }
+
+    /// Like `new`, also adding every transform of `symmetry` of each window. The
+    /// patterns of the untransformed sample keep the ids `new` gives them.
+    pub fn with_symmetry(img: &Image, n: usize, symmetry: Symmetry) -> Result<Self> {
+        let w = img.get_width() as usize;
+        let h = img.get_height() as usize;
+        if !(MIN_PATTERN_SIZE..=MAX_PATTERN_SIZE).contains(&n) || n > w || n > h {
+            return Err(Error::InvalidDimensions {
+                width: img.get_width(),
+                height: img.get_height(),
+                depth: 1,
+            });
+        }
+
+        let palette = Palette::from_image(img);
+        let mut patterns = Vec::new();
+        let mut pattern_ids: HashMap<Vec<Tile>, usize> = HashMap::new();
+        let mut weights = Weights::new();
+        for transform in symmetry.transforms() {
+            for y in 0..=(h - n) {
+                for x in 0..=(w - n) {
+                    let pattern = get_pattern(img, &palette, x, y, n);
+                    let pattern = transform_pattern(&pattern, n, &transform);
+                    let id = *pattern_ids.entry(pattern.clone()).or_insert_with(|| {
+                        patterns.push(pattern);
+                        patterns.len() - 1
+                    });
+                    weights.add(Tile(id), 1.0);
+                }
+            }
+        }
+
+        Ok(OverlappingModel {
+            n,
+            palette,
+            patterns,
+            weights,
+        })
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// A pattern can be at a direction of another when, once shifted by one
+    /// cell in that direction, every overlapping pixel has the same color.
+    pub fn extract_rules(&self) -> HashSet<Rule> {
+        let mut rules = HashSet::new();
+        for (i, curr) in self.patterns.iter().enumerate() {
+            for (j, adj) in self.patterns.iter().enumerate() {
+                for direction in Direction::all().iter() {
+                    let (dx, dy) = direction.offset();
+                    if agrees(curr, adj, self.n, dx, dy) {
+                        rules.insert(Rule::new(Tile(j), Tile(i), direction.clone()));
+                    }
+                }
+            }
+        }
+        rules
+    }
+}
+
+fn get_pattern(img: &Image, palette: &Palette, x: usize, y: usize, n: usize) -> Vec<Tile> {
+    let mut pattern = Vec::with_capacity(n * n);
+    for dy in 0..n {
+        for dx in 0..n {
+            let pixel = img.get_pixel((x + dx) as u32, (y + dy) as u32);
+            pattern.push(
+                palette
+                    .tile(pixel)
+                    .expect("Palette is built from the same image"),
+            );
+        }
+    }
+    pattern
+}
+
+fn transform_pattern(pattern: &[Tile], n: usize, transform: &Transform) -> Vec<Tile> {
+    let mut transformed = pattern.to_vec();
+    for y in 0..n {
+        for x in 0..n {
+            let (tx, ty) = transform.coords((x as u32, y as u32), (n as u32, n as u32));
+            transformed[ty as usize * n + tx as usize] = pattern[y * n + x].clone();
+        }
+    }
+    transformed
+}
+
+/// Whether `adj`, placed at offset (`dx`, `dy`) from `curr`, matches it on their overlap.
+fn agrees(curr: &[Tile], adj: &[Tile], n: usize, dx: i32, dy: i32) -> bool {
+    let n = n as i32;
+    for y in dy.max(0)..(n + dy).min(n) {
+        for x in dx.max(0)..(n + dx).min(n) {
+            let curr_index = (y * n + x) as usize;
+            let adj_index = ((y - dy) * n + (x - dx)) as usize;
+            if curr[curr_index] != adj[adj_index] {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+impl Render for OverlappingModel {
+    /// Renders a collapsed state using the top-left pixel of each cell's pattern.
+    fn render(&self, state: &State) -> Result<Image> {
+        state.check_single_layer()?;
+        let mut img = Image::new(state.width as u32, state.height as u32);
+        for x in 0..state.width {
+            for y in 0..state.height {
+                let possibilities = state.get(x, y);
+                if possibilities.len() != 1 {
+                    return Err(Error::Unrenderable);
+                }
+                let tile = possibilities.iter().next().ok_or(Error::Unrenderable)?;
+                let pattern = self.patterns.get(tile.id()).ok_or(Error::Unrenderable)?;
+                let pixel = self.palette.pixel(&pattern[0]).ok_or(Error::Unrenderable)?;
+                img.set_pixel(x as u32, y as u32, pixel);
+            }
+        }
+        Ok(img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLUE, RED};
+
+    use crate::state::HashSetExt;
+
+    use super::*;
+
+    fn stripes_3x3() -> Image {
+        let mut img = Image::new(3, 3);
+        for (x, y) in img.coordinates() {
+            img.set_pixel(x, y, if x == 1 { BLUE } else { RED });
+        }
+        img
+    }
+
+    #[test]
+    fn test_invalid_size() {
+        let img = stripes_3x3();
+        assert!(OverlappingModel::new(&img, 1).is_err());
+        assert!(OverlappingModel::new(&img, 4).is_err());
+        assert!(OverlappingModel::new(&img, 6).is_err());
+    }
+
+    #[test]
+    fn test_extract_patterns() {
+        let img = stripes_3x3();
+        let model = OverlappingModel::new(&img, 2).unwrap();
+
+        assert_eq!(model.tile_count(), 2);
+        assert_eq!(model.patterns[0], vec![Tile(0), Tile(1), Tile(0), Tile(1)]);
+        assert_eq!(model.patterns[1], vec![Tile(1), Tile(0), Tile(1), Tile(0)]);
+        assert_eq!(model.weights.get(&Tile(0)), 2.0);
+        assert_eq!(model.weights.get(&Tile(1)), 2.0);
+    }
+
+    #[test]
+    fn test_with_symmetry() {
+        let img = stripes_3x3();
+        let model = OverlappingModel::with_symmetry(&img, 2, Symmetry::Rotations).unwrap();
+
+        // The vertical stripes, then the same stripes lying down.
+        assert_eq!(model.tile_count(), 4);
+        assert_eq!(model.patterns[0], vec![Tile(0), Tile(1), Tile(0), Tile(1)]);
+        assert_eq!(model.patterns[2], vec![Tile(0), Tile(0), Tile(1), Tile(1)]);
+        assert_eq!(model.weights.get(&Tile(0)), 4.0);
+
+        let mirrored = OverlappingModel::with_symmetry(&img, 2, Symmetry::HorizontalMirror);
+        assert_eq!(mirrored.unwrap().tile_count(), 2);
+    }
+
+    #[test]
+    fn test_extract_rules() {
+        let img = stripes_3x3();
+        let model = OverlappingModel::new(&img, 2).unwrap();
+
+        let rules = model.extract_rules();
+        let expected = HashSet::from_all(vec![
+            Rule::new(Tile(0), Tile(0), Direction::Up),
+            Rule::new(Tile(0), Tile(0), Direction::Down),
+            Rule::new(Tile(1), Tile(1), Direction::Up),
+            Rule::new(Tile(1), Tile(1), Direction::Down),
+            Rule::new(Tile(1), Tile(0), Direction::Right),
+            Rule::new(Tile(0), Tile(1), Direction::Right),
+            Rule::new(Tile(1), Tile(0), Direction::Left),
+            Rule::new(Tile(0), Tile(1), Direction::Left),
+        ]);
+        assert_eq!(rules, expected);
+    }
+
+    #[test]
+    fn test_render() {
+        let img = stripes_3x3();
+        let model = OverlappingModel::new(&img, 2).unwrap();
+
+        let mut state = State::new(2, 1, &HashSet::from_all(vec![Tile(0), Tile(1)]));
+        state.possible_vals.set(0, 0, HashSet::new().with(Tile(1)));
+        assert!(matches!(model.render(&state), Err(Error::Unrenderable)));
+
+        state.possible_vals.set(1, 0, HashSet::new().with(Tile(0)));
+        let res = model.render(&state).unwrap();
+        assert_eq!(res.get_pixel(0, 0), BLUE);
+        assert_eq!(res.get_pixel(1, 0), RED);
+    }
+}
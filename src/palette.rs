@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+
+use bmp::{Image, Pixel};
+
+use crate::enums::Tile;
+
+/// Maps every distinct color of a sample image to a `Tile` id and back.
+///
+/// Ids are assigned in the order colors are first met, scanning the image
+/// row by row from the top-left corner.
+#[derive(Clone, Debug, Default)]
+pub struct Palette {
+    colors: Vec<Pixel>,
+    ids: HashMap<(u8, u8, u8), usize>,
+}
+
+impl Palette {
+    pub fn new() -> Self {
+        Palette::default()
+    }
+
+    pub fn from_image(img: &Image) -> Self {
+        let mut palette = Palette::new();
+        for (x, y) in img.coordinates() {
+            palette.insert(img.get_pixel(x, y));
+        }
+        palette
+    }
+
+    /// Returns the tile of `pixel`, registering the color if it is new.
+    pub fn insert(&mut self, pixel: Pixel) -> Tile {
+        if let Some(tile) = self.tile(pixel) {
+            return tile;
+        }
+        let id = self.colors.len();
+        self.colors.push(pixel);
+        self.ids.insert(key(pixel), id);
+        Tile(id)
+    }
+
+    pub fn tile(&self, pixel: Pixel) -> Option<Tile> {
+        self.ids.get(&key(pixel)).map(|id| Tile(*id))
+    }
+
+    pub fn pixel(&self, tile: &Tile) -> Option<Pixel> {
+        self.colors.get(tile.id()).copied()
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> {
+        (0..self.colors.len()).map(Tile)
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+}
+
+fn key(pixel: Pixel) -> (u8, u8, u8) {
+    (pixel.r, pixel.g, pixel.b)
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLUE, LIME, RED};
+
+    use super::*;
+
+    #[test]
+    fn test_from_image() {
+        let mut img = Image::new(2, 2);
+        img.set_pixel(0, 0, RED);
+        img.set_pixel(1, 0, Pixel::new(12, 34, 56));
+        img.set_pixel(0, 1, RED);
+        img.set_pixel(1, 1, BLUE);
+
+        let palette = Palette::from_image(&img);
+        assert_eq!(palette.len(), 3);
+        assert_eq!(palette.tile(RED), Some(Tile(0)));
+        assert_eq!(palette.tile(Pixel::new(12, 34, 56)), Some(Tile(1)));
+        assert_eq!(palette.tile(BLUE), Some(Tile(2)));
+        assert_eq!(palette.tile(LIME), None);
+        assert_eq!(palette.pixel(&Tile(1)), Some(Pixel::new(12, 34, 56)));
+        assert_eq!(palette.pixel(&Tile(3)), None);
+    }
+
+    #[test]
+    fn test_insert_existing() {
+        let mut palette = Palette::new();
+        assert_eq!(palette.insert(RED), Tile(0));
+        assert_eq!(palette.insert(LIME), Tile(1));
+        assert_eq!(palette.insert(RED), Tile(0));
+        assert_eq!(palette.len(), 2);
+    }
+}
@@ -0,0 +1,226 @@
+use std::{cmp::Ordering, collections::BinaryHeap};
+
+use rand::Rng;
+
+use crate::{
+    domain::Domain,
+    enums::{Heuristic, Tile},
+    weights::Weights,
+};
+
+/// The open cells, those with more than one possibility, ordered by a `Heuristic` so
+/// the solver finds the next one to collapse without scanning the grid.
+///
+/// Cells are addressed by their index in `PossibleVals::cells`, which calls `update`
+/// on every change. A change pushes a new entry rather than moving the old one, so
+/// `lowest` skips the entries a later change made stale.
+#[derive(Clone, Debug)]
+pub struct Ranking {
+    heuristic: Heuristic,
+    weights: Weights,
+    /// Weight sums of each cell, only kept up to date for `Heuristic::Entropy`.
+    sums: Vec<WeightSums>,
+    /// Tie-break of each cell, drawn once.
+    noise: Vec<f64>,
+    /// Changes of each cell so far, an entry is stale once its cell moved past it.
+    versions: Vec<usize>,
+    heap: BinaryHeap<Entry>,
+}
+
+#[derive(Clone, Debug)]
+struct Entry {
+    priority: f64,
+    index: usize,
+    version: usize,
+}
+
+/// Reversed, so the top of the heap is the lowest priority.
+impl Ord for Entry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .total_cmp(&self.priority)
+            .then_with(|| other.index.cmp(&self.index))
+    }
+}
+
+impl PartialOrd for Entry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Entry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Entry {}
+
+/// The weights of the possibilities of a cell summed up, with and without their log,
+/// so its Shannon entropy follows without going over its tiles again. Tiles without a
+/// positive weight are left out.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WeightSums {
+    pub weight: f64,
+    pub weight_log_weight: f64,
+}
+
+impl WeightSums {
+    pub fn new(possibilities: &Domain, weights: &Weights) -> Self {
+        let mut sums = WeightSums::default();
+        for tile in possibilities {
+            sums.add(&tile, weights);
+        }
+        sums
+    }
+
+    pub fn add(&mut self, tile: &Tile, weights: &Weights) {
+        let weight = weights.get(tile);
+        if weight > 0.0 {
+            self.weight += weight;
+            self.weight_log_weight += weight * weight.ln();
+        }
+    }
+
+    pub fn remove(&mut self, tile: &Tile, weights: &Weights) {
+        let weight = weights.get(tile);
+        if weight > 0.0 {
+            self.weight -= weight;
+            self.weight_log_weight -= weight * weight.ln();
+        }
+    }
+
+    /// `-Σ p log p`, with `p` each weight over their sum.
+    pub fn entropy(&self) -> f64 {
+        if self.weight <= 0.0 {
+            return 0.0;
+        }
+        self.weight.ln() - self.weight_log_weight / self.weight
+    }
+}
+
+impl Ranking {
+    /// Ranks `cells`, breaking ties with a small random noise drawn from `rng`.
+    pub fn new(
+        heuristic: &Heuristic,
+        weights: &Weights,
+        cells: &[Domain],
+        rng: &mut impl Rng,
+    ) -> Self {
+        let mut ranking = Ranking {
+            heuristic: heuristic.clone(),
+            weights: weights.clone(),
+            sums: match heuristic {
+                Heuristic::Count => Vec::new(),
+                Heuristic::Entropy => cells
+                    .iter()
+                    .map(|possibilities| WeightSums::new(possibilities, weights))
+                    .collect(),
+            },
+            noise: cells.iter().map(|_| rng.gen::<f64>() * 1e-6).collect(),
+            versions: vec![0; cells.len()],
+            heap: BinaryHeap::new(),
+        };
+        let entries = cells
+            .iter()
+            .enumerate()
+            .filter(|(_, possibilities)| possibilities.len() > 1)
+            .map(|(index, possibilities)| ranking.entry(index, possibilities))
+            .collect::<Vec<_>>();
+        ranking.heap = BinaryHeap::from(entries);
+        ranking
+    }
+
+    fn entry(&self, index: usize, possibilities: &Domain) -> Entry {
+        let priority = match self.heuristic {
+            Heuristic::Count => possibilities.len() as f64,
+            Heuristic::Entropy => self.sums[index].entropy(),
+        };
+        Entry {
+            priority: priority + self.noise[index],
+            index,
+            version: self.versions[index],
+        }
+    }
+
+    /// Records that the cell at `index` went from the `before` possibilities to the
+    /// `after` ones. Only the tiles that differ are summed again.
+    pub fn update(&mut self, index: usize, before: &Domain, after: &Domain) {
+        if self.heuristic == Heuristic::Entropy {
+            let sums = &mut self.sums[index];
+            for tile in &before.difference(after) {
+                sums.remove(&tile, &self.weights);
+            }
+            for tile in &after.difference(before) {
+                sums.add(&tile, &self.weights);
+            }
+        }
+        self.versions[index] += 1;
+        if after.len() > 1 {
+            let entry = self.entry(index, after);
+            self.heap.push(entry);
+        }
+        // Drop the stale entries once they outnumber the cells, so backtracking
+        // back and forth cannot grow the heap without bound.
+        if self.heap.len() > 2 * self.versions.len() {
+            let versions = &self.versions;
+            self.heap
+                .retain(|entry| entry.version == versions[entry.index]);
+        }
+    }
+
+    /// Index of the open cell ranked lowest, `None` when there is none.
+    pub fn lowest(&mut self) -> Option<usize> {
+        while let Some(entry) = self.heap.peek() {
+            if entry.version == self.versions[entry.index] {
+                return Some(entry.index);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::state::HashSetExt;
+
+    use super::*;
+
+    fn domain(tiles: Vec<Tile>) -> Domain {
+        Domain::from(HashSet::from_all(tiles))
+    }
+
+    #[test]
+    fn test_update_sums() {
+        let weights = Weights::new().with(Tile::RED, 5.0).with(Tile::BLUE, 0.5);
+        let all = domain(vec![Tile::RED, Tile::GREEN, Tile::BLUE]);
+        let cells = vec![all.clone(), domain(vec![Tile::RED, Tile::GREEN])];
+        let mut ranking = Ranking::new(
+            &Heuristic::Entropy,
+            &weights,
+            &cells,
+            &mut rand::thread_rng(),
+        );
+        assert_eq!(ranking.lowest(), Some(1));
+
+        // Each update gives the sums of the new possibilities, whether tiles were
+        // removed or added back.
+        let mut before = all;
+        for (after, lowest) in [
+            (domain(vec![Tile::RED, Tile::BLUE]), 0),
+            (domain(vec![Tile::GREEN, Tile::BLUE]), 1),
+        ] {
+            ranking.update(0, &before, &after);
+            let expected = WeightSums::new(&after, &weights);
+            assert!((ranking.sums[0].weight - expected.weight).abs() < 1e-9);
+            assert!((ranking.sums[0].entropy() - expected.entropy()).abs() < 1e-9);
+            assert_eq!(ranking.lowest(), Some(lowest));
+            before = after;
+        }
+    }
+}
@@ -0,0 +1,103 @@
+/// How the contradiction budget grows from one attempt to the next.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum RestartSchedule {
+    /// Every attempt gets the same budget.
+    #[default]
+    Fixed,
+    /// The budget follows the Luby sequence: 1, 1, 2, 1, 1, 2, 4, 1, ...
+    Luby,
+    /// The budget is multiplied by `factor` after every attempt.
+    Geometric { factor: f64 },
+}
+
+/// Bounds the work done before generation gives up.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RestartPolicy {
+    /// Number of attempts, each one starting from an empty grid.
+    pub max_attempts: usize,
+    /// Contradictions allowed in the first attempt before restarting, `None` for no limit.
+    pub max_contradictions: Option<usize>,
+    pub schedule: RestartSchedule,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy {
+            max_attempts: 10,
+            max_contradictions: Some(1000),
+            schedule: RestartSchedule::Fixed,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Contradictions allowed during the `attempt`-th attempt, starting at 0.
+    pub fn contradiction_budget(&self, attempt: usize) -> Option<usize> {
+        let base = self.max_contradictions?;
+        let budget = match &self.schedule {
+            RestartSchedule::Fixed => base,
+            RestartSchedule::Luby => base.saturating_mul(luby(attempt + 1)),
+            RestartSchedule::Geometric { factor } => {
+                (base as f64 * factor.powi(attempt as i32)).ceil() as usize
+            }
+        };
+        Some(budget.max(1))
+    }
+}
+
+/// The `i`-th term of the Luby sequence, starting at 1.
+pub fn luby(i: usize) -> usize {
+    let mut i = i.max(1);
+    loop {
+        let mut k = 1;
+        while (1 << k) - 1 < i {
+            k += 1;
+        }
+        if i == (1 << k) - 1 {
+            return 1 << (k - 1);
+        }
+        i -= (1 << (k - 1)) - 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_luby() {
+        let sequence = (1..=15).map(luby).collect::<Vec<_>>();
+        assert_eq!(sequence, vec![1, 1, 2, 1, 1, 2, 4, 1, 1, 2, 1, 1, 2, 4, 8]);
+    }
+
+    #[test]
+    fn test_contradiction_budget() {
+        let fixed = RestartPolicy {
+            max_attempts: 3,
+            max_contradictions: Some(10),
+            schedule: RestartSchedule::Fixed,
+        };
+        assert_eq!(fixed.contradiction_budget(0), Some(10));
+        assert_eq!(fixed.contradiction_budget(2), Some(10));
+
+        let luby = RestartPolicy {
+            schedule: RestartSchedule::Luby,
+            ..fixed.clone()
+        };
+        assert_eq!(luby.contradiction_budget(2), Some(20));
+        assert_eq!(luby.contradiction_budget(6), Some(40));
+
+        let geometric = RestartPolicy {
+            schedule: RestartSchedule::Geometric { factor: 1.5 },
+            ..fixed.clone()
+        };
+        assert_eq!(geometric.contradiction_budget(0), Some(10));
+        assert_eq!(geometric.contradiction_budget(2), Some(23));
+
+        let unlimited = RestartPolicy {
+            max_contradictions: None,
+            ..fixed
+        };
+        assert_eq!(unlimited.contradiction_budget(5), None);
+    }
+}
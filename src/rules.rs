@@ -1,9 +1,15 @@
-use std::collections::HashSet;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
 
 use bmp::{Image, Pixel};
 
 use crate::{
-    enums::{Direction, Tile},
+    domain::Domain,
+    enums::{Direction, Neighbourhood, Tile},
+    error::{Error, Result},
+    palette::Palette,
     state::{contains_invalid_tiles, PossibleVals, State},
 };
 
@@ -24,94 +30,280 @@ impl Rule {
     }
 }
 
-pub type AdjacentPixels = (
-    Option<HashSet<Tile>>,
-    Option<HashSet<Tile>>,
-    Option<HashSet<Tile>>,
-    Option<HashSet<Tile>>,
-    Option<HashSet<Tile>>,
-);
+#[cfg(test)]
+impl Rule {
+    /// Red and blue always side by side, so the only solutions are checkerboards.
+    pub fn checkerboard() -> HashSet<Rule> {
+        let mut rules = HashSet::new();
+        for direction in Direction::all() {
+            rules.insert(Rule::new(Tile::RED, Tile::BLUE, direction.clone()));
+            rules.insert(Rule::new(Tile::BLUE, Tile::RED, direction));
+        }
+        rules
+    }
+}
 
-pub fn extract_rules(img: &Image) -> HashSet<Rule> {
-    let mut rules: HashSet<Rule> = HashSet::new();
-    for (x, y) in img.coordinates() {
-        let curr_tile = img.get_pixel(x, y);
-        let (up, down, left, right) = get_image_adjacent_pixels(img, x, y);
+/// The rules compiled into a dense table: `propagator[direction][tile]` holds the tiles
+/// allowed on the cell at `direction` of `tile`. Directions without any rule are
+/// unconstrained.
+///
+/// Built once per rule set, so propagating never walks the rules themselves.
+#[derive(Clone, Debug, Default)]
+pub struct Adjacency {
+    propagator: Vec<Vec<Domain>>,
+    constrained: Vec<bool>,
+    /// Slot of each graph edge direction the rules use, after the grid directions.
+    edge_slots: HashMap<Direction, usize>,
+    tiles: Domain,
+}
 
-        if let Some(up) = up {
-            rules.insert(Rule::new(up.into(), curr_tile.into(), Direction::Up));
+impl Adjacency {
+    pub fn new(rules: &HashSet<Rule>) -> Self {
+        let tile_count = rules
+            .iter()
+            .map(|rule| rule.curr_tile.id().max(rule.adj_tile.id()) + 1)
+            .max()
+            .unwrap_or(0);
+        // Graph edges come after the grid directions, one slot per label and way, so
+        // the table grows with the labels in use rather than with their values.
+        let mut edge_slots = HashMap::new();
+        for rule in rules {
+            if rule.direction.index().is_none() && !edge_slots.contains_key(&rule.direction) {
+                edge_slots.insert(
+                    rule.direction.clone(),
+                    Direction::every().len() + edge_slots.len(),
+                );
+            }
         }
-        if let Some(down) = down {
-            rules.insert(Rule::new(down.into(), curr_tile.into(), Direction::Down));
+        let direction_count = Direction::every().len() + edge_slots.len();
+        let mut adjacency = Adjacency {
+            propagator: vec![vec![Domain::with_capacity(tile_count); tile_count]; direction_count],
+            constrained: vec![false; direction_count],
+            edge_slots,
+            tiles: Domain::with_capacity(tile_count),
+        };
+        for rule in rules {
+            let direction = adjacency
+                .slot(&rule.direction)
+                .expect("Every direction of the rules has a slot");
+            adjacency.propagator[direction][rule.adj_tile.id()].insert(rule.curr_tile.clone());
+            adjacency.constrained[direction] = true;
+            adjacency.tiles.insert(rule.curr_tile.clone());
+            adjacency.tiles.insert(rule.adj_tile.clone());
         }
-        if let Some(left) = left {
-            rules.insert(Rule::new(left.into(), curr_tile.into(), Direction::Left));
+        adjacency
+    }
+
+    /// Row of `direction` in the tables, `None` for an edge the rules never use.
+    fn slot(&self, direction: &Direction) -> Option<usize> {
+        direction
+            .index()
+            .or_else(|| self.edge_slots.get(direction).copied())
+    }
+
+    pub fn is_constrained(&self, direction: &Direction) -> bool {
+        self.slot(direction)
+            .and_then(|slot| self.constrained.get(slot))
+            .copied()
+            .unwrap_or(false)
+    }
+
+    /// Every tile appearing in the rules.
+    pub fn tiles(&self) -> &Domain {
+        &self.tiles
+    }
+
+    /// Tiles allowed at `direction` of `tile`, `None` for a tile outside the rules.
+    pub fn allowed(&self, direction: &Direction, tile: &Tile) -> Option<&Domain> {
+        self.propagator.get(self.slot(direction)?)?.get(tile.id())
+    }
+
+    /// Tiles allowed at `direction` of a cell that can still be any of `possibilities`.
+    pub fn supported(&self, direction: &Direction, possibilities: &Domain) -> Domain {
+        let Some(allowed) = self
+            .slot(direction)
+            .and_then(|slot| self.propagator.get(slot))
+        else {
+            return Domain::new();
+        };
+        let mut supported = Domain::with_capacity(allowed.len());
+        for tile in possibilities {
+            if let Some(allowed) = allowed.get(tile.id()) {
+                supported.union_with(allowed);
+            }
         }
-        if let Some(right) = right {
-            rules.insert(Rule::new(right.into(), curr_tile.into(), Direction::Right));
+        supported
+    }
+}
+
+pub type AdjacentPixels = (
+    Option<Domain>,
+    Option<Domain>,
+    Option<Domain>,
+    Option<Domain>,
+    Option<Domain>,
+);
+
+/// Fails with `Error::InvalidColor` on the first pixel whose color is not in `palette`.
+pub fn extract_rules(img: &Image, palette: &Palette) -> Result<HashSet<Rule>> {
+    extract_rules_with_neighbourhood(img, palette, &Neighbourhood::VonNeumann)
+}
+
+/// Like `extract_rules`, learning which tiles can be next to each other in every
+/// direction of `neighbourhood`.
+pub fn extract_rules_with_neighbourhood(
+    img: &Image,
+    palette: &Palette,
+    neighbourhood: &Neighbourhood,
+) -> Result<HashSet<Rule>> {
+    for (x, y) in img.coordinates() {
+        let pixel = img.get_pixel(x, y);
+        if palette.tile(pixel).is_none() {
+            return Err(Error::InvalidColor { x, y, pixel });
         }
     }
+    let to_tile = |pixel: Pixel| palette.tile(pixel).expect("Every color was checked above");
+    let (w, h) = (img.get_width() as i64, img.get_height() as i64);
+    let mut rules: HashSet<Rule> = HashSet::new();
+    for (x, y) in img.coordinates() {
+        let curr_tile = to_tile(img.get_pixel(x, y));
+        for direction in neighbourhood.directions() {
+            let (dx, dy) = direction.offset();
+            let (nx, ny) = (x as i64 + dx as i64, y as i64 + dy as i64);
+            if nx < 0 || ny < 0 || nx >= w || ny >= h {
+                continue;
+            }
+            let adj_tile = to_tile(img.get_pixel(nx as u32, ny as u32));
+            rules.insert(Rule::new(adj_tile, curr_tile.clone(), direction));
+        }
+    }
+
+    Ok(rules)
+}
 
-    rules
+/// Runs one sweep over the grid, reading the neighbours from `curr_state`.
+///
+/// A possibility is kept when, in every constrained direction, the neighbouring cell
+/// still has at least one tile it is allowed to be next to. Fails with
+/// `Error::Unsatisfiable` when a cell is left without any possibility.
+pub fn apply_rules(curr_state: &State, rules: &HashSet<Rule>) -> Result<State> {
+    apply_adjacency(curr_state, &Adjacency::new(rules))
 }
 
-pub fn apply_rules(curr_state: &State, rules: &HashSet<Rule>) -> Option<State> {
-    let w = curr_state.width;
-    let h = curr_state.height;
+/// Like `apply_rules`, with the rules already compiled.
+pub fn apply_adjacency(curr_state: &State, adjacency: &Adjacency) -> Result<State> {
     let mut new_possibilities = curr_state.possible_vals.clone();
 
-    for x in 0..w {
-        for y in 0..h {
-            let possibilities = &curr_state.possible_vals.get(x, y);
-            if possibilities.len() == 1 {
+    let possible_vals = &curr_state.possible_vals;
+    for (x, y) in possible_vals.coords() {
+        let mut new_tile_possibilities = possible_vals.get(x, y).clone();
+        for (direction, (nx, ny)) in possible_vals.topology.neighbours((x, y)) {
+            if !adjacency.is_constrained(&direction.opposite()) {
                 continue;
             }
+            // Tiles that can sit on our side of the neighbour.
+            let supported = adjacency.supported(&direction.opposite(), possible_vals.get(nx, ny));
+            new_tile_possibilities.intersect_with(&supported);
+        }
+        new_possibilities.set(x, y, new_tile_possibilities);
+    }
 
-            let (_, up, down, left, right) =
-                get_possibilities_adjacent_pixels(&curr_state.possible_vals, x, y);
-            let mut new_tile_possibilities: HashSet<Tile> = HashSet::new();
-            for possibility in possibilities.iter() {
-                let mut valid = true;
-                for rule in rules.iter() {
-                    if rule.curr_tile != *possibility {
-                        continue;
-                    }
-                    if rule.direction == Direction::Up && up.is_some() {
-                        if !up.as_ref().unwrap().contains(&rule.adj_tile) {
-                            valid = false;
-                            break;
-                        }
-                    } else if rule.direction == Direction::Down && down.is_some() {
-                        if !down.as_ref().unwrap().contains(&rule.adj_tile) {
-                            valid = false;
-                            break;
-                        }
-                    } else if rule.direction == Direction::Left && left.is_some() {
-                        if !left.as_ref().unwrap().contains(&rule.adj_tile) {
-                            valid = false;
-                            break;
-                        }
-                    } else if rule.direction == Direction::Right
-                        && right.is_some()
-                        && !right.as_ref().unwrap().contains(&rule.adj_tile)
-                    {
-                        valid = false;
-                        break;
-                    }
-                }
-                if valid {
-                    new_tile_possibilities.insert(possibility.clone());
-                }
+    if contains_invalid_tiles(&new_possibilities) {
+        return Err(Error::Unsatisfiable);
+    }
+
+    Ok(curr_state.with_possibilities(new_possibilities))
+}
+
+/// Previous possibilities of the cells modified by `propagate`, oldest first.
+pub type Trail = Vec<((usize, usize), Domain)>;
+
+/// Propagates the constraints from the `changed` cells until nothing changes anymore.
+///
+/// Only the neighbours of a cell whose possibilities shrank are revisited, so the cost
+/// is proportional to the number of removals rather than to the grid size. The
+/// previous possibilities of every modified cell are pushed on `trail` so the caller
+/// can `undo` them. Returns `false` as soon as a cell runs out of possibilities.
+pub fn propagate(
+    possible_vals: &mut PossibleVals,
+    adjacency: &Adjacency,
+    changed: Vec<(usize, usize)>,
+    trail: &mut Trail,
+) -> bool {
+    let topology = Arc::clone(&possible_vals.topology);
+    let (w, h) = topology.size();
+    // Indexed like the cells of `PossibleVals`.
+    let index = |(x, y): (usize, usize)| x * h + y;
+    let mut worklist = std::mem::take(&mut possible_vals.worklist);
+    worklist.resize(w * h);
+    for cell in changed {
+        worklist.push(index(cell), cell);
+    }
+
+    let mut consistent = true;
+    while let Some((x, y)) = worklist.pop() {
+        topology.for_each_neighbour((x, y), &mut |direction, (nx, ny)| {
+            if !consistent || !adjacency.is_constrained(direction) {
+                return;
             }
-            new_possibilities.set(x, y, new_tile_possibilities);
+            let supported = adjacency.supported(direction, possible_vals.get(x, y));
+            let Some(before) = possible_vals.intersect(nx, ny, &supported) else {
+                return;
+            };
+            trail.push(((nx, ny), before));
+            if possible_vals.get(nx, ny).is_empty() {
+                consistent = false;
+                return;
+            }
+            worklist.push(index((nx, ny)), (nx, ny));
+        });
+        if !consistent {
+            break;
         }
     }
+    worklist.clear();
+    possible_vals.worklist = worklist;
+    consistent
+}
 
-    if contains_invalid_tiles(&new_possibilities) {
-        return None;
+/// The cells `propagate` still has to visit. `PossibleVals` keeps it between calls so
+/// the flags are allocated once rather than on every call.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Worklist {
+    queue: VecDeque<(usize, (usize, usize))>,
+    /// Whether each cell, by index, is in `queue`.
+    queued: Vec<bool>,
+}
+
+impl Worklist {
+    fn resize(&mut self, cell_count: usize) {
+        self.queued.resize(cell_count, false);
+    }
+
+    fn push(&mut self, index: usize, cell: (usize, usize)) {
+        if !self.queued[index] {
+            self.queued[index] = true;
+            self.queue.push_back((index, cell));
+        }
+    }
+
+    fn pop(&mut self) -> Option<(usize, usize)> {
+        let (index, cell) = self.queue.pop_front()?;
+        self.queued[index] = false;
+        Some(cell)
+    }
+
+    /// Empties the queue left by a contradiction.
+    fn clear(&mut self) {
+        while self.pop().is_some() {}
     }
+}
 
-    Some(curr_state.with_possibilities(new_possibilities))
+/// Restores the possibilities recorded on `trail`, most recent first.
+pub fn undo(possible_vals: &mut PossibleVals, trail: &mut Trail) {
+    while let Some(((x, y), possibilities)) = trail.pop() {
+        possible_vals.set(x, y, possibilities);
+    }
 }
 
 pub fn get_image_adjacent_pixels(
@@ -144,39 +336,49 @@ pub fn get_image_adjacent_pixels(
     (up, down, left, right)
 }
 
+/// The possibilities of the cell at (`x`, `y`) and of its neighbours. A neighbour is
+/// `None` outside the grid, which never happens on a periodic grid. Only the sides of a
+/// square cell are listed, see `get_possibilities_neighbours` for the other tilings.
 pub fn get_possibilities_adjacent_pixels(
     possible_vals: &PossibleVals,
     x: usize,
     y: usize,
 ) -> AdjacentPixels {
-    let size = possible_vals.size();
-    if size.is_none() {
+    if possible_vals.size().is_none() {
         return (None, None, None, None, None);
     }
-    let (w, h) = size.unwrap();
-
-    let curr = possible_vals.get(x, y).clone();
-    let up = if y > 0 {
-        Some(possible_vals.get(x, y - 1).clone())
-    } else {
-        None
-    };
-    let down = if y < h - 1 {
-        Some(possible_vals.get(x, y + 1).clone())
-    } else {
-        None
+    let adjacent = |direction: Direction| {
+        possible_vals
+            .neighbour(x, y, &direction)
+            .map(|(nx, ny)| possible_vals.get(nx, ny).clone())
     };
-    let left = if x > 0 {
-        Some(possible_vals.get(x - 1, y).clone())
-    } else {
-        None
-    };
-    let right = if x < w - 1 {
-        Some(possible_vals.get(x + 1, y).clone())
-    } else {
-        None
-    };
-    (Some(curr), up, down, left, right)
+    (
+        Some(possible_vals.get(x, y).clone()),
+        adjacent(Direction::Up),
+        adjacent(Direction::Down),
+        adjacent(Direction::Left),
+        adjacent(Direction::Right),
+    )
+}
+
+/// The possibilities of the neighbours of the cell at (`x`, `y`) in every direction of
+/// the topology, `None` outside the grid.
+pub fn get_possibilities_neighbours(
+    possible_vals: &PossibleVals,
+    x: usize,
+    y: usize,
+) -> Vec<(Direction, Option<Domain>)> {
+    possible_vals
+        .topology
+        .directions()
+        .into_iter()
+        .map(|direction| {
+            let possibilities = possible_vals
+                .neighbour(x, y, &direction)
+                .map(|(nx, ny)| possible_vals.get(nx, ny).clone());
+            (direction, possibilities)
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -188,43 +390,114 @@ mod tests {
     #[test]
     fn test_extract_rules() {
         let mut img = Image::new(2, 2);
-        img.set_pixel(0, 0, Tile::Red.into());
-        img.set_pixel(1, 0, Tile::Blue.into());
-        img.set_pixel(0, 1, Tile::Red.into());
-        img.set_pixel(1, 1, Tile::Red.into());
+        img.set_pixel(0, 0, bmp::consts::RED);
+        img.set_pixel(1, 0, bmp::consts::BLUE);
+        img.set_pixel(0, 1, bmp::consts::RED);
+        img.set_pixel(1, 1, bmp::consts::RED);
+
+        let mut palette = Palette::new();
+        palette.insert(bmp::consts::RED);
+        palette.insert(bmp::consts::LIME);
+        palette.insert(bmp::consts::BLUE);
 
-        let rules = extract_rules(&img);
+        let rules = extract_rules(&img, &palette).unwrap();
         let expected = HashSet::from_all(vec![
-            Rule::new(Tile::Red, Tile::Red, Direction::Down),
-            Rule::new(Tile::Red, Tile::Red, Direction::Up),
-            Rule::new(Tile::Red, Tile::Red, Direction::Right),
-            Rule::new(Tile::Red, Tile::Red, Direction::Left),
-            Rule::new(Tile::Red, Tile::Blue, Direction::Left),
-            Rule::new(Tile::Blue, Tile::Red, Direction::Right),
-            Rule::new(Tile::Red, Tile::Blue, Direction::Down),
-            Rule::new(Tile::Blue, Tile::Red, Direction::Up),
+            Rule::new(Tile::RED, Tile::RED, Direction::Down),
+            Rule::new(Tile::RED, Tile::RED, Direction::Up),
+            Rule::new(Tile::RED, Tile::RED, Direction::Right),
+            Rule::new(Tile::RED, Tile::RED, Direction::Left),
+            Rule::new(Tile::RED, Tile::BLUE, Direction::Left),
+            Rule::new(Tile::BLUE, Tile::RED, Direction::Right),
+            Rule::new(Tile::RED, Tile::BLUE, Direction::Down),
+            Rule::new(Tile::BLUE, Tile::RED, Direction::Up),
         ]);
 
         assert_eq!(rules, expected);
     }
 
+    #[test]
+    fn test_extract_rules_invalid_color() {
+        let mut img = Image::new(2, 1);
+        img.set_pixel(0, 0, bmp::consts::RED);
+        img.set_pixel(1, 0, bmp::consts::BLUE);
+
+        let mut palette = Palette::new();
+        palette.insert(bmp::consts::RED);
+
+        let res = extract_rules(&img, &palette);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidColor { x: 1, y: 0, pixel }) if pixel == bmp::consts::BLUE
+        ));
+    }
+
+    #[test]
+    fn test_extract_rules_moore() {
+        // A staircase: red on the diagonal, blue elsewhere.
+        let mut img = Image::new(2, 2);
+        img.set_pixel(0, 0, bmp::consts::RED);
+        img.set_pixel(1, 0, bmp::consts::BLUE);
+        img.set_pixel(0, 1, bmp::consts::BLUE);
+        img.set_pixel(1, 1, bmp::consts::RED);
+        let palette = Palette::from_image(&img);
+
+        let rules =
+            extract_rules_with_neighbourhood(&img, &palette, &Neighbourhood::Moore).unwrap();
+        assert_eq!(rules.len(), 12);
+        assert!(rules.contains(&Rule::new(Tile(0), Tile(0), Direction::DownRight)));
+        assert!(rules.contains(&Rule::new(Tile(1), Tile(1), Direction::UpRight)));
+        assert!(!rules.contains(&Rule::new(Tile(1), Tile(0), Direction::DownRight)));
+        assert!(extract_rules(&img, &palette).unwrap().is_subset(&rules));
+    }
+
+    #[test]
+    fn test_adjacency() {
+        let adjacency = Adjacency::new(&HashSet::from_all(vec![
+            Rule::new(Tile::RED, Tile::BLUE, Direction::Left),
+            Rule::new(Tile::GREEN, Tile::BLUE, Direction::Left),
+            Rule::new(Tile::BLUE, Tile::RED, Direction::Right),
+        ]));
+
+        assert!(adjacency.is_constrained(&Direction::Left));
+        assert!(!adjacency.is_constrained(&Direction::Up));
+        assert_eq!(adjacency.tiles().len(), 3);
+        assert_eq!(
+            adjacency.allowed(&Direction::Left, &Tile::BLUE).unwrap(),
+            HashSet::from_all(vec![Tile::RED, Tile::GREEN])
+        );
+        assert!(adjacency
+            .allowed(&Direction::Left, &Tile::RED)
+            .unwrap()
+            .is_empty());
+        assert_eq!(adjacency.allowed(&Direction::Left, &Tile(7)), None);
+        assert_eq!(
+            adjacency.supported(
+                &Direction::Right,
+                &HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into()
+            ),
+            HashSet::from_all(vec![Tile::BLUE])
+        );
+    }
+
     mod get_possibilities_adjacent_pixels {
         use rstest::{fixture, rstest};
 
-        use crate::state::print_tile_possibilities_and_adjacents;
+        use std::sync::Arc;
+
+        use crate::{enums::Tiling, state::print_tile_possibilities_and_adjacents, topology::Grid};
 
         use super::*;
 
         #[fixture]
         fn possible_vals_1x1() -> PossibleVals {
-            PossibleVals::from(vec![vec![HashSet::from_all(vec![Tile::Red, Tile::Green])]])
+            PossibleVals::from(vec![vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN])]])
         }
 
         #[fixture]
         fn possible_vals_1x2() -> PossibleVals {
             PossibleVals::from(vec![vec![
-                HashSet::from_all(vec![Tile::Red, Tile::Green]),
-                HashSet::from_all(vec![Tile::Red, Tile::Green]),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN]),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN]),
             ]])
         }
 
@@ -232,53 +505,89 @@ mod tests {
         fn possible_vals_3x3() -> PossibleVals {
             PossibleVals::from(vec![
                 vec![
-                    HashSet::from_all(vec![Tile::Green]),
-                    HashSet::from_all(vec![Tile::Red, Tile::Green]),
-                    HashSet::from_all(vec![Tile::Red]),
+                    HashSet::from_all(vec![Tile::GREEN]),
+                    HashSet::from_all(vec![Tile::RED, Tile::GREEN]),
+                    HashSet::from_all(vec![Tile::RED]),
                 ],
                 vec![
-                    HashSet::from_all(vec![Tile::Green, Tile::Blue]),
-                    HashSet::from_all(vec![Tile::Red, Tile::Green, Tile::Blue]),
-                    HashSet::from_all(vec![Tile::Red, Tile::Blue]),
+                    HashSet::from_all(vec![Tile::GREEN, Tile::BLUE]),
+                    HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE]),
+                    HashSet::from_all(vec![Tile::RED, Tile::BLUE]),
                 ],
                 vec![
-                    HashSet::from_all(vec![Tile::Blue]),
-                    HashSet::from_all(vec![Tile::Green, Tile::Red]),
-                    HashSet::from_all(vec![Tile::Red, Tile::Green]),
+                    HashSet::from_all(vec![Tile::BLUE]),
+                    HashSet::from_all(vec![Tile::GREEN, Tile::RED]),
+                    HashSet::from_all(vec![Tile::RED, Tile::GREEN]),
                 ],
             ])
         }
 
         #[fixture]
         fn possible_vals_0x1() -> PossibleVals {
-            PossibleVals::from(vec![])
+            PossibleVals::from(Vec::<Vec<Domain>>::new())
         }
 
         #[rstest]
         fn test_1x1(possible_vals_1x1: PossibleVals) {
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_1x1, 0, 0);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
+            assert_eq!(
+                curr,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
             assert_eq!(up, None);
             assert_eq!(down, None);
             assert_eq!(left, None);
             assert_eq!(right, None);
         }
 
+        #[rstest]
+        fn test_periodic(mut possible_vals_3x3: PossibleVals) {
+            possible_vals_3x3.topology = Arc::new(Grid {
+                periodic: true,
+                ..Grid::new(3, 3)
+            });
+            let (curr, up, down, left, right) =
+                get_possibilities_adjacent_pixels(&possible_vals_3x3, 0, 0);
+            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::GREEN]).into()));
+            assert_eq!(up, Some(HashSet::from_all(vec![Tile::RED]).into()));
+            assert_eq!(
+                down,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
+            assert_eq!(left, Some(HashSet::from_all(vec![Tile::BLUE]).into()));
+            assert_eq!(
+                right,
+                Some(HashSet::from_all(vec![Tile::GREEN, Tile::BLUE]).into())
+            );
+        }
+
         #[rstest]
         fn test_1x2(possible_vals_1x2: PossibleVals) {
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_1x2, 0, 0);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
+            assert_eq!(
+                curr,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
             assert_eq!(up, None);
-            assert_eq!(down, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
+            assert_eq!(
+                down,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
             assert_eq!(left, None);
             assert_eq!(right, None);
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_1x2, 0, 1);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
-            assert_eq!(up, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
+            assert_eq!(
+                curr,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
+            assert_eq!(
+                up,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
             assert_eq!(down, None);
             assert_eq!(left, None);
             assert_eq!(right, None);
@@ -289,92 +598,146 @@ mod tests {
             print_tile_possibilities_and_adjacents(&possible_vals_3x3, 0, 0);
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 0, 0);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Green])));
+            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::GREEN]).into()));
             assert_eq!(up, None);
-            assert_eq!(down, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
+            assert_eq!(
+                down,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
             assert_eq!(left, None);
             assert_eq!(
                 right,
-                Some(HashSet::from_all(vec![Tile::Green, Tile::Blue]))
+                Some(HashSet::from_all(vec![Tile::GREEN, Tile::BLUE]).into())
             );
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 1, 0);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Green, Tile::Blue])));
+            assert_eq!(
+                curr,
+                Some(HashSet::from_all(vec![Tile::GREEN, Tile::BLUE]).into())
+            );
             assert_eq!(up, None);
             assert_eq!(
                 down,
-                Some(HashSet::from_all(vec![Tile::Red, Tile::Green, Tile::Blue]))
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE]).into())
             );
-            assert_eq!(left, Some(HashSet::from_all(vec![Tile::Green])));
-            assert_eq!(right, Some(HashSet::from_all(vec![Tile::Blue])));
+            assert_eq!(left, Some(HashSet::from_all(vec![Tile::GREEN]).into()));
+            assert_eq!(right, Some(HashSet::from_all(vec![Tile::BLUE]).into()));
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 2, 0);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Blue])));
+            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::BLUE]).into()));
             assert_eq!(up, None);
-            assert_eq!(down, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
-            assert_eq!(left, Some(HashSet::from_all(vec![Tile::Green, Tile::Blue])));
+            assert_eq!(
+                down,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
+            assert_eq!(
+                left,
+                Some(HashSet::from_all(vec![Tile::GREEN, Tile::BLUE]).into())
+            );
             assert_eq!(right, None);
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 0, 1);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
-            assert_eq!(up, Some(HashSet::from_all(vec![Tile::Green])));
-            assert_eq!(down, Some(HashSet::from_all(vec![Tile::Red])));
+            assert_eq!(
+                curr,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
+            assert_eq!(up, Some(HashSet::from_all(vec![Tile::GREEN]).into()));
+            assert_eq!(down, Some(HashSet::from_all(vec![Tile::RED]).into()));
             assert_eq!(left, None);
             assert_eq!(
                 right,
-                Some(HashSet::from_all(vec![Tile::Red, Tile::Green, Tile::Blue]))
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE]).into())
             );
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 1, 1);
             assert_eq!(
                 curr,
-                Some(HashSet::from_all(vec![Tile::Red, Tile::Green, Tile::Blue]))
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE]).into())
+            );
+            assert_eq!(
+                up,
+                Some(HashSet::from_all(vec![Tile::GREEN, Tile::BLUE]).into())
+            );
+            assert_eq!(
+                down,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::BLUE]).into())
+            );
+            assert_eq!(
+                left,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
+            assert_eq!(
+                right,
+                Some(HashSet::from_all(vec![Tile::GREEN, Tile::RED]).into())
             );
-            assert_eq!(up, Some(HashSet::from_all(vec![Tile::Green, Tile::Blue])));
-            assert_eq!(down, Some(HashSet::from_all(vec![Tile::Red, Tile::Blue])));
-            assert_eq!(left, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
-            assert_eq!(right, Some(HashSet::from_all(vec![Tile::Green, Tile::Red])));
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 2, 1);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Green, Tile::Red])));
-            assert_eq!(up, Some(HashSet::from_all(vec![Tile::Blue])));
-            assert_eq!(down, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
+            assert_eq!(
+                curr,
+                Some(HashSet::from_all(vec![Tile::GREEN, Tile::RED]).into())
+            );
+            assert_eq!(up, Some(HashSet::from_all(vec![Tile::BLUE]).into()));
+            assert_eq!(
+                down,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
             assert_eq!(
                 left,
-                Some(HashSet::from_all(vec![Tile::Red, Tile::Green, Tile::Blue]))
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE]).into())
             );
             assert_eq!(right, None);
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 0, 2);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Red])));
-            assert_eq!(up, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
+            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::RED]).into()));
+            assert_eq!(
+                up,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
             assert_eq!(down, None);
             assert_eq!(left, None);
-            assert_eq!(right, Some(HashSet::from_all(vec![Tile::Red, Tile::Blue])));
+            assert_eq!(
+                right,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::BLUE]).into())
+            );
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 1, 2);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Red, Tile::Blue])));
+            assert_eq!(
+                curr,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::BLUE]).into())
+            );
             assert_eq!(
                 up,
-                Some(HashSet::from_all(vec![Tile::Red, Tile::Green, Tile::Blue]))
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE]).into())
             );
             assert_eq!(down, None);
-            assert_eq!(left, Some(HashSet::from_all(vec![Tile::Red])));
-            assert_eq!(right, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
+            assert_eq!(left, Some(HashSet::from_all(vec![Tile::RED]).into()));
+            assert_eq!(
+                right,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
 
             let (curr, up, down, left, right) =
                 get_possibilities_adjacent_pixels(&possible_vals_3x3, 2, 2);
-            assert_eq!(curr, Some(HashSet::from_all(vec![Tile::Red, Tile::Green])));
-            assert_eq!(up, Some(HashSet::from_all(vec![Tile::Green, Tile::Red])));
+            assert_eq!(
+                curr,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::GREEN]).into())
+            );
+            assert_eq!(
+                up,
+                Some(HashSet::from_all(vec![Tile::GREEN, Tile::RED]).into())
+            );
             assert_eq!(down, None);
-            assert_eq!(left, Some(HashSet::from_all(vec![Tile::Red, Tile::Blue])));
+            assert_eq!(
+                left,
+                Some(HashSet::from_all(vec![Tile::RED, Tile::BLUE]).into())
+            );
             assert_eq!(right, None);
         }
 
@@ -388,209 +751,255 @@ mod tests {
             assert_eq!(left, None);
             assert_eq!(right, None);
         }
+
+        #[rstest]
+        fn test_hex(mut possible_vals_3x3: PossibleVals) {
+            possible_vals_3x3.topology = Arc::new(Grid {
+                tiling: Tiling::Hex,
+                ..Grid::new(3, 3)
+            });
+            let (_, up, down, _, _) = get_possibilities_adjacent_pixels(&possible_vals_3x3, 1, 1);
+            assert_eq!((up, down), (None, None));
+
+            // The middle row is shifted to the right.
+            let neighbours = get_possibilities_neighbours(&possible_vals_3x3, 1, 1);
+            let expected = [
+                (Direction::Left, vec![Tile::RED, Tile::GREEN]),
+                (Direction::Right, vec![Tile::GREEN, Tile::RED]),
+                (Direction::UpLeft, vec![Tile::GREEN, Tile::BLUE]),
+                (Direction::UpRight, vec![Tile::BLUE]),
+                (Direction::DownLeft, vec![Tile::RED, Tile::BLUE]),
+                (Direction::DownRight, vec![Tile::RED, Tile::GREEN]),
+            ];
+            assert_eq!(neighbours.len(), expected.len());
+            for ((direction, possibilities), (expected_direction, tiles)) in
+                neighbours.into_iter().zip(expected)
+            {
+                assert_eq!(direction, expected_direction);
+                assert_eq!(possibilities, Some(HashSet::from_all(tiles).into()));
+            }
+        }
     }
 
     mod apply_rules {
         use rstest::{fixture, rstest};
 
-        use crate::state::State;
+        use crate::{generator::Generator, state::State};
 
         use super::*;
 
         #[fixture]
         fn state_1x1_rg() -> State {
-            State::new(1, 1, &HashSet::from_all(vec![Tile::Red, Tile::Green]))
+            State::new(1, 1, &HashSet::from_all(vec![Tile::RED, Tile::GREEN]))
         }
 
         #[fixture]
         fn state_2x2_rg() -> State {
-            State::new(2, 2, &HashSet::from_all(vec![Tile::Red, Tile::Green]))
+            State::new(2, 2, &HashSet::from_all(vec![Tile::RED, Tile::GREEN]))
         }
 
         #[fixture]
         fn state_3x3_rg() -> State {
-            State::new(3, 3, &HashSet::from_all(vec![Tile::Red, Tile::Green]))
+            State::new(3, 3, &HashSet::from_all(vec![Tile::RED, Tile::GREEN]))
         }
 
         #[fixture]
         fn rules_red_green_ud() -> HashSet<Rule> {
             HashSet::from_all(vec![
-                Rule::new(Tile::Red, Tile::Red, Direction::Down),
-                Rule::new(Tile::Red, Tile::Red, Direction::Up),
-                Rule::new(Tile::Green, Tile::Green, Direction::Down),
-                Rule::new(Tile::Green, Tile::Green, Direction::Up),
+                Rule::new(Tile::RED, Tile::RED, Direction::Down),
+                Rule::new(Tile::RED, Tile::RED, Direction::Up),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::Down),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::Up),
             ])
         }
 
         #[fixture]
         fn rules_red_green_lr() -> HashSet<Rule> {
             HashSet::from_all(vec![
-                Rule::new(Tile::Red, Tile::Red, Direction::Left),
-                Rule::new(Tile::Red, Tile::Red, Direction::Right),
-                Rule::new(Tile::Green, Tile::Green, Direction::Left),
-                Rule::new(Tile::Green, Tile::Green, Direction::Right),
+                Rule::new(Tile::RED, Tile::RED, Direction::Left),
+                Rule::new(Tile::RED, Tile::RED, Direction::Right),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::Left),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::Right),
             ])
         }
 
         #[fixture]
         fn rules_red_udlr() -> HashSet<Rule> {
             HashSet::from_all(vec![
-                Rule::new(Tile::Red, Tile::Red, Direction::Down),
-                Rule::new(Tile::Red, Tile::Red, Direction::Up),
-                Rule::new(Tile::Red, Tile::Red, Direction::Left),
-                Rule::new(Tile::Red, Tile::Red, Direction::Right),
+                Rule::new(Tile::RED, Tile::RED, Direction::Down),
+                Rule::new(Tile::RED, Tile::RED, Direction::Up),
+                Rule::new(Tile::RED, Tile::RED, Direction::Left),
+                Rule::new(Tile::RED, Tile::RED, Direction::Right),
             ])
         }
 
+        /// Anything goes next to red, but two greens are never neighbours.
+        #[fixture]
+        fn rules_no_green_pair() -> HashSet<Rule> {
+            let mut rules = HashSet::new();
+            for direction in Direction::all() {
+                rules.insert(Rule::new(Tile::RED, Tile::RED, direction.clone()));
+                rules.insert(Rule::new(Tile::RED, Tile::GREEN, direction.clone()));
+                rules.insert(Rule::new(Tile::GREEN, Tile::RED, direction));
+            }
+            rules
+        }
+
         #[rstest]
         fn test_1x1(state_1x1_rg: State, rules_red_green_ud: HashSet<Rule>) {
             let new_state = apply_rules(&state_1x1_rg, &rules_red_green_ud);
-            assert!(new_state.is_some());
+            assert!(new_state.is_ok());
             let new_state = new_state.unwrap();
             assert_eq!(
-                new_state.possible_vals.inner[0][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(0, 0),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
         }
 
         #[rstest]
         fn test_2x2_no_collapse(state_2x2_rg: State, rules_red_green_ud: HashSet<Rule>) {
             let new_state = apply_rules(&state_2x2_rg, &rules_red_green_ud);
-            assert!(new_state.is_some());
+            assert!(new_state.is_ok());
             let new_state = new_state.unwrap();
             assert_eq!(
-                new_state.possible_vals.inner[0][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(0, 0),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 0),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[0][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(0, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
         }
 
         #[rstest]
         fn test_2x2_collapse(mut state_2x2_rg: State, rules_red_green_ud: HashSet<Rule>) {
-            state_2x2_rg.possible_vals.inner[0][0] = HashSet::from_all(vec![Tile::Red]);
+            state_2x2_rg
+                .possible_vals
+                .set(0, 0, HashSet::from_all(vec![Tile::RED]));
 
             let new_state = apply_rules(&state_2x2_rg, &rules_red_green_ud);
-            assert!(new_state.is_some());
+            assert!(new_state.is_ok());
             let new_state = new_state.unwrap();
 
             assert_eq!(
-                new_state.possible_vals.inner[0][0],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 0),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[0][1],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 1),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
         }
 
         #[rstest]
         fn test_3x3_collapse_vertical(mut state_3x3_rg: State, rules_red_green_ud: HashSet<Rule>) {
-            state_3x3_rg.possible_vals.inner[0][0] = HashSet::from_all(vec![Tile::Red]);
-            state_3x3_rg.possible_vals.inner[2][0] = HashSet::from_all(vec![Tile::Green]);
+            state_3x3_rg
+                .possible_vals
+                .set(0, 0, HashSet::from_all(vec![Tile::RED]));
+            state_3x3_rg
+                .possible_vals
+                .set(2, 0, HashSet::from_all(vec![Tile::GREEN]));
 
             let new_state = apply_rules(&state_3x3_rg, &rules_red_green_ud);
-            assert!(new_state.is_some());
+            assert!(new_state.is_ok());
             let new_state = new_state.unwrap();
 
             assert_eq!(
-                new_state.possible_vals.inner[0][0],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[0][1],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 1),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[0][2],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(0, 2),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
             assert_eq!(
-                new_state.possible_vals.inner[1][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 0),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][2],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 2),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
             assert_eq!(
-                new_state.possible_vals.inner[2][0],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(2, 0),
+                HashSet::from_all(vec![Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][1],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(2, 1),
+                HashSet::from_all(vec![Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][2],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(2, 2),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
             let new_state = apply_rules(&new_state, &rules_red_green_ud);
-            assert!(new_state.is_some());
+            assert!(new_state.is_ok());
             let new_state = new_state.unwrap();
 
             assert_eq!(
-                new_state.possible_vals.inner[0][0],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[0][1],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 1),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[0][2],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 2),
+                HashSet::from_all(vec![Tile::RED])
             );
 
             assert_eq!(
-                new_state.possible_vals.inner[1][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 0),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][2],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 2),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
             assert_eq!(
-                new_state.possible_vals.inner[2][0],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(2, 0),
+                HashSet::from_all(vec![Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][1],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(2, 1),
+                HashSet::from_all(vec![Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][2],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(2, 2),
+                HashSet::from_all(vec![Tile::GREEN])
             );
         }
 
@@ -599,144 +1008,356 @@ mod tests {
             mut state_3x3_rg: State,
             rules_red_green_lr: HashSet<Rule>,
         ) {
-            state_3x3_rg.possible_vals.inner[0][0] = HashSet::from_all(vec![Tile::Red]);
-            state_3x3_rg.possible_vals.inner[0][2] = HashSet::from_all(vec![Tile::Green]);
+            state_3x3_rg
+                .possible_vals
+                .set(0, 0, HashSet::from_all(vec![Tile::RED]));
+            state_3x3_rg
+                .possible_vals
+                .set(0, 2, HashSet::from_all(vec![Tile::GREEN]));
 
             let new_state = apply_rules(&state_3x3_rg, &rules_red_green_lr);
-            assert!(new_state.is_some());
+            assert!(new_state.is_ok());
             let new_state = new_state.unwrap();
             assert_eq!(
-                new_state.possible_vals.inner[0][0],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][0],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(1, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(2, 0),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
             assert_eq!(
-                new_state.possible_vals.inner[0][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(0, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(2, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
             assert_eq!(
-                new_state.possible_vals.inner[0][2],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(0, 2),
+                HashSet::from_all(vec![Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][2],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(1, 2),
+                HashSet::from_all(vec![Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][2],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(2, 2),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
             let new_state = apply_rules(&new_state, &rules_red_green_lr);
-            assert!(new_state.is_some());
+            assert!(new_state.is_ok());
             let new_state = new_state.unwrap();
 
             assert_eq!(
-                new_state.possible_vals.inner[0][0],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][0],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(1, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][0],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(2, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
 
             assert_eq!(
-                new_state.possible_vals.inner[0][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(0, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(1, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][1],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(2, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
             assert_eq!(
-                new_state.possible_vals.inner[0][2],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(0, 2),
+                HashSet::from_all(vec![Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][2],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(1, 2),
+                HashSet::from_all(vec![Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[2][2],
-                HashSet::from_all(vec![Tile::Green])
+                new_state.possible_vals.get(2, 2),
+                HashSet::from_all(vec![Tile::GREEN])
             );
         }
 
         #[rstest]
         fn test_3x3_collapse_all_red(mut state_3x3_rg: State, rules_red_udlr: HashSet<Rule>) {
-            state_3x3_rg.possible_vals.inner[1][1] = HashSet::from_all(vec![Tile::Red]);
+            state_3x3_rg
+                .possible_vals
+                .set(1, 1, HashSet::from_all(vec![Tile::RED]));
 
             println!("Initial state: {:?}", state_3x3_rg);
 
             let new_state = apply_rules(&state_3x3_rg, &rules_red_udlr);
-            assert!(new_state.is_some());
+            assert!(new_state.is_ok());
             let new_state = new_state.unwrap();
 
             println!("After first apply rule: {:?}", new_state);
 
+            // No rule allows green next to anything, so it is dropped from every cell.
+            for x in 0..3 {
+                for y in 0..3 {
+                    assert_eq!(
+                        new_state.possible_vals.get(x, y),
+                        HashSet::from_all(vec![Tile::RED])
+                    );
+                }
+            }
+        }
+
+        #[rstest]
+        fn test_kept_with_one_allowed_neighbour(rules_no_green_pair: HashSet<Rule>) {
+            let mut state = State::new(1, 2, &HashSet::from_all(vec![Tile::RED, Tile::GREEN]));
+            state
+                .possible_vals
+                .set(0, 0, HashSet::from_all(vec![Tile::RED]));
+
+            // Red also allows a green neighbour, but one remaining red neighbour is enough.
+            let new_state = apply_rules(&state, &rules_no_green_pair).unwrap();
             assert_eq!(
-                new_state.possible_vals.inner[0][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
-            );
-            assert_eq!(
-                new_state.possible_vals.inner[1][0],
-                HashSet::from_all(vec![Tile::Red])
-            );
-            assert_eq!(
-                new_state.possible_vals.inner[2][0],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                new_state.possible_vals.get(0, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
 
+            state
+                .possible_vals
+                .set(0, 0, HashSet::from_all(vec![Tile::GREEN]));
+            let new_state = apply_rules(&state, &rules_no_green_pair).unwrap();
             assert_eq!(
-                new_state.possible_vals.inner[0][1],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 1),
+                HashSet::from_all(vec![Tile::RED])
             );
+        }
+
+        #[rstest]
+        fn test_directed_rule() {
+            // Green can only be below red.
+            let rules = HashSet::from_all(vec![
+                Rule::new(Tile::GREEN, Tile::RED, Direction::Down),
+                Rule::new(Tile::RED, Tile::GREEN, Direction::Up),
+            ]);
+            let mut state = State::new(1, 2, &HashSet::from_all(vec![Tile::RED, Tile::GREEN]));
+            state
+                .possible_vals
+                .set(0, 1, HashSet::from_all(vec![Tile::GREEN]));
+
+            let new_state = apply_rules(&state, &rules).unwrap();
             assert_eq!(
-                new_state.possible_vals.inner[1][1],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
+
+            state
+                .possible_vals
+                .set(0, 1, HashSet::from_all(vec![Tile::RED]));
+            assert!(matches!(
+                apply_rules(&state, &rules),
+                Err(Error::Unsatisfiable)
+            ));
+        }
+
+        #[rstest]
+        fn test_rules_on_one_side() {
+            // Only the cell below is ever constrained, never the one above.
+            let rules = HashSet::from_all(vec![
+                Rule::new(Tile::RED, Tile::RED, Direction::Down),
+                Rule::new(Tile::RED, Tile::GREEN, Direction::Down),
+                Rule::new(Tile::GREEN, Tile::RED, Direction::Down),
+            ]);
+            let mut state = State::new(1, 3, &HashSet::from_all(vec![Tile::RED, Tile::GREEN]));
+            let new_state = apply_rules(&state, &rules).unwrap();
+            assert_eq!(new_state.get_total_entropy(), 6);
+
+            state
+                .possible_vals
+                .set(0, 1, HashSet::from_all(vec![Tile::GREEN]));
+            let new_state = apply_rules(&state, &rules).unwrap();
             assert_eq!(
-                new_state.possible_vals.inner[2][1],
-                HashSet::from_all(vec![Tile::Red])
+                new_state.possible_vals.get(0, 2),
+                HashSet::from_all(vec![Tile::RED])
             );
+            assert_eq!(new_state.possible_vals.get(0, 0).len(), 2);
+
+            let generated = Generator::builder()
+                .size(1, 3)
+                .rules(rules)
+                .build()
+                .unwrap()
+                .generate();
+            assert!(generated.is_ok());
+        }
 
+        #[rstest]
+        fn test_contradiction(mut state_2x2_rg: State, rules_red_green_ud: HashSet<Rule>) {
+            state_2x2_rg
+                .possible_vals
+                .set(0, 0, HashSet::from_all(vec![Tile::RED]));
+            state_2x2_rg
+                .possible_vals
+                .set(0, 1, HashSet::from_all(vec![Tile::GREEN]));
+
+            assert!(apply_rules(&state_2x2_rg, &rules_red_green_ud).is_err());
+        }
+    }
+
+    mod propagate {
+        use rstest::{fixture, rstest};
+
+        use super::*;
+
+        #[fixture]
+        fn possible_vals_3x3_rg() -> PossibleVals {
+            PossibleVals::from(vec![
+                vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN]); 3];
+                3
+            ])
+        }
+
+        #[fixture]
+        fn rules_red_green_ud() -> HashSet<Rule> {
+            HashSet::from_all(vec![
+                Rule::new(Tile::RED, Tile::RED, Direction::Down),
+                Rule::new(Tile::RED, Tile::RED, Direction::Up),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::Down),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::Up),
+            ])
+        }
+
+        #[rstest]
+        fn test_propagates_whole_column(
+            mut possible_vals_3x3_rg: PossibleVals,
+            rules_red_green_ud: HashSet<Rule>,
+        ) {
+            possible_vals_3x3_rg.set(1, 0, HashSet::from_all(vec![Tile::RED]));
+            let adjacency = Adjacency::new(&rules_red_green_ud);
+
+            let mut trail = Vec::new();
+            assert!(propagate(
+                &mut possible_vals_3x3_rg,
+                &adjacency,
+                vec![(1, 0)],
+                &mut trail
+            ));
+            assert_eq!(trail.len(), 2);
+            for y in 0..3 {
+                assert_eq!(
+                    possible_vals_3x3_rg.get(1, y),
+                    HashSet::from_all(vec![Tile::RED])
+                );
+                assert_eq!(
+                    possible_vals_3x3_rg.get(0, y),
+                    HashSet::from_all(vec![Tile::RED, Tile::GREEN])
+                );
+                assert_eq!(
+                    possible_vals_3x3_rg.get(2, y),
+                    HashSet::from_all(vec![Tile::RED, Tile::GREEN])
+                );
+            }
+        }
+
+        #[rstest]
+        fn test_contradiction(
+            mut possible_vals_3x3_rg: PossibleVals,
+            rules_red_green_ud: HashSet<Rule>,
+        ) {
+            possible_vals_3x3_rg.set(0, 0, HashSet::from_all(vec![Tile::RED]));
+            possible_vals_3x3_rg.set(0, 2, HashSet::from_all(vec![Tile::GREEN]));
+            let adjacency = Adjacency::new(&rules_red_green_ud);
+
+            let mut trail = Vec::new();
+            assert!(!propagate(
+                &mut possible_vals_3x3_rg,
+                &adjacency,
+                vec![(0, 0), (0, 2)],
+                &mut trail
+            ));
+
+            undo(&mut possible_vals_3x3_rg, &mut trail);
+            assert!(trail.is_empty());
             assert_eq!(
-                new_state.possible_vals.inner[0][2],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                possible_vals_3x3_rg.get(0, 1),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
             assert_eq!(
-                new_state.possible_vals.inner[1][2],
-                HashSet::from_all(vec![Tile::Red])
+                possible_vals_3x3_rg.get(0, 0),
+                HashSet::from_all(vec![Tile::RED])
             );
+        }
+
+        #[rstest]
+        fn test_diagonal() {
+            // Red can only be diagonal to red, and is unconstrained on the sides.
+            let mut possible_vals =
+                PossibleVals::from(vec![
+                    vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN]); 2];
+                    2
+                ]);
+            possible_vals.set(0, 0, HashSet::from_all(vec![Tile::RED]));
+            let adjacency = Adjacency::new(&HashSet::from_all(vec![
+                Rule::new(Tile::RED, Tile::RED, Direction::DownRight),
+                Rule::new(Tile::RED, Tile::RED, Direction::UpLeft),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::DownRight),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::UpLeft),
+            ]));
+
+            assert!(propagate(
+                &mut possible_vals,
+                &adjacency,
+                vec![(0, 0)],
+                &mut Vec::new()
+            ));
+            assert_eq!(possible_vals.get(1, 1), HashSet::from_all(vec![Tile::RED]));
+            assert_eq!(possible_vals.get(1, 0).len(), 2);
+        }
+
+        #[rstest]
+        fn test_directed_rule() {
+            // Green can only be below red: the cell above a red cell cannot be
+            // green, while the cell below it keeps both options.
+            let mut possible_vals =
+                PossibleVals::from(vec![vec![
+                    HashSet::from_all(vec![Tile::RED, Tile::GREEN]);
+                    3
+                ]]);
+            possible_vals.set(0, 1, HashSet::from_all(vec![Tile::RED]));
+            let adjacency = Adjacency::new(&HashSet::from_all(vec![
+                Rule::new(Tile::GREEN, Tile::RED, Direction::Down),
+                Rule::new(Tile::RED, Tile::GREEN, Direction::Up),
+                Rule::new(Tile::RED, Tile::RED, Direction::Up),
+                Rule::new(Tile::RED, Tile::RED, Direction::Down),
+            ]));
+
+            assert!(propagate(
+                &mut possible_vals,
+                &adjacency,
+                vec![(0, 1)],
+                &mut Vec::new()
+            ));
+            assert_eq!(possible_vals.get(0, 0), HashSet::from_all(vec![Tile::RED]));
             assert_eq!(
-                new_state.possible_vals.inner[2][2],
-                HashSet::from_all(vec![Tile::Red, Tile::Green])
+                possible_vals.get(0, 2),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN])
             );
         }
     }
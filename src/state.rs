@@ -1,63 +1,188 @@
-use std::{collections::HashSet, path::PathBuf};
+use std::{collections::HashSet, sync::Arc};
 
 use bmp::Image;
 
 use crate::{
-    enums::Tile,
-    files::delete_files_in_dir,
-    rules::{apply_rules, get_possibilities_adjacent_pixels, Rule},
+    domain::Domain,
+    enums::{Direction, Heuristic, Tile, Tiling},
+    error::{Error, Result},
+    observer::{NoObserver, Observer},
+    palette::Palette,
+    ranking::{Ranking, WeightSums},
+    restart::RestartPolicy,
+    rules::{
+        apply_adjacency, get_possibilities_neighbours, propagate, undo, Adjacency, Rule, Trail,
+        Worklist,
+    },
+    topology::{Grid, Topology},
+    weights::Weights,
 };
-use rand::prelude::SliceRandom;
+use rand::{prelude::SliceRandom, rngs::StdRng, Rng, SeedableRng};
 use std::fmt::Debug;
-use std::io::Write;
 
+/// The possibilities of every cell, laid out by `topology`.
 #[derive(Clone)]
 pub struct PossibleVals {
-    pub inner: Vec<Vec<HashSet<Tile>>>,
+    cells: Vec<Domain>,
+    pub topology: Arc<dyn Topology>,
+    /// Cells left with other than exactly one possibility.
+    open: usize,
+    /// Set by `rank`, kept up to date by every change of a cell.
+    ranking: Option<Ranking>,
+    /// Scratch space of `propagate`, kept between calls.
+    pub(crate) worklist: Worklist,
 }
 
 impl PossibleVals {
-    pub fn set(&mut self, x: usize, y: usize, value: HashSet<Tile>) {
-        self.inner[x][y] = value;
+    /// Every cell of `topology` starts with `possibilities`.
+    pub fn new(topology: Arc<dyn Topology>, possibilities: Domain) -> Self {
+        let (w, h) = topology.size();
+        PossibleVals::from_cells(vec![possibilities; w * h], topology)
     }
 
-    pub fn get(&self, x: usize, y: usize) -> HashSet<Tile> {
-        self.inner[x][y].clone()
+    fn from_cells(cells: Vec<Domain>, topology: Arc<dyn Topology>) -> Self {
+        PossibleVals {
+            open: cells.iter().filter(|cell| cell.len() != 1).count(),
+            cells,
+            topology,
+            ranking: None,
+            worklist: Worklist::default(),
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> Result<usize> {
+        let (w, h) = self.topology.size();
+        if x >= w || y >= h {
+            return Err(Error::OutOfBounds { x, y });
+        }
+        Ok(x * h + y)
+    }
+
+    /// Panics when (`x`, `y`) is outside the grid.
+    pub fn set(&mut self, x: usize, y: usize, value: impl Into<Domain>) {
+        let index = self.index(x, y).unwrap_or_else(|err| panic!("{}", err));
+        let before = std::mem::replace(&mut self.cells[index], value.into());
+        self.changed(index, &before);
+    }
+
+    /// Keeps only the possibilities of the cell at (`x`, `y`) that are in `tiles`, and
+    /// returns the previous ones when that removed any. Panics when (`x`, `y`) is
+    /// outside the grid.
+    pub fn intersect(&mut self, x: usize, y: usize, tiles: &Domain) -> Option<Domain> {
+        let index = self.index(x, y).unwrap_or_else(|err| panic!("{}", err));
+        if self.cells[index].is_subset(tiles) {
+            return None;
+        }
+        let before = self.cells[index].clone();
+        self.cells[index].intersect_with(tiles);
+        self.changed(index, &before);
+        Some(before)
+    }
+
+    fn changed(&mut self, index: usize, before: &Domain) {
+        let after = &self.cells[index];
+        self.open = self.open + usize::from(after.len() != 1) - usize::from(before.len() != 1);
+        if let Some(ranking) = &mut self.ranking {
+            ranking.update(index, before, after);
+        }
+    }
+
+    /// Panics when (`x`, `y`) is outside the grid, see `try_get`.
+    pub fn get(&self, x: usize, y: usize) -> &Domain {
+        self.try_get(x, y).unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// The possibilities of the cell at (`x`, `y`), `Error::OutOfBounds` outside the
+    /// grid.
+    pub fn try_get(&self, x: usize, y: usize) -> Result<&Domain> {
+        Ok(&self.cells[self.index(x, y)?])
+    }
+
+    /// Whether every cell has exactly one possibility left.
+    pub fn is_collapsed(&self) -> bool {
+        self.open == 0
+    }
+
+    /// Orders the open cells by `heuristic` from now on, ties broken at random, so
+    /// `lowest` does not need to scan the grid.
+    pub fn rank(&mut self, heuristic: &Heuristic, weights: &Weights, rng: &mut impl Rng) {
+        self.ranking = Some(Ranking::new(heuristic, weights, &self.cells, rng));
+    }
+
+    /// The open cell `rank` puts first, `None` when no cell has more than one
+    /// possibility or the cells were never ranked.
+    pub fn lowest(&mut self) -> Option<(usize, usize)> {
+        let index = self.ranking.as_mut()?.lowest()?;
+        let (_, h) = self.topology.size();
+        Some((index / h, index % h))
+    }
+
+    /// The possibilities of every cell, in the order of `coords`.
+    pub fn cells(&self) -> &[Domain] {
+        &self.cells
     }
 
     pub fn size(&self) -> Option<(usize, usize)> {
-        let w = self.inner.len();
+        let (w, h) = self.topology.size();
         if w == 0 {
             return None;
         }
-        let h = self.inner[0].len();
         Some((w, h))
     }
+
+    /// Every cell, column by column.
+    pub fn coords(&self) -> impl Iterator<Item = (usize, usize)> {
+        let (w, h) = self.topology.size();
+        (0..w).flat_map(move |x| (0..h).map(move |y| (x, y)))
+    }
+
+    /// Coordinates of the cell next to (`x`, `y`) in `direction`, if there is one.
+    pub fn neighbour(&self, x: usize, y: usize, direction: &Direction) -> Option<(usize, usize)> {
+        self.topology
+            .neighbours((x, y))
+            .into_iter()
+            .find(|(neighbour_direction, _)| neighbour_direction == direction)
+            .map(|(_, neighbour)| neighbour)
+    }
+}
+
+/// A square grid, one inner vector per column.
+impl From<Vec<Vec<Domain>>> for PossibleVals {
+    fn from(inner: Vec<Vec<Domain>>) -> Self {
+        let grid = Grid::new(inner.len(), inner.first().map_or(0, Vec::len));
+        PossibleVals::from_cells(inner.into_iter().flatten().collect(), Arc::new(grid))
+    }
 }
 
 impl From<Vec<Vec<HashSet<Tile>>>> for PossibleVals {
     fn from(inner: Vec<Vec<HashSet<Tile>>>) -> Self {
-        PossibleVals { inner }
+        PossibleVals::from(
+            inner
+                .into_iter()
+                .map(|row| row.into_iter().map(Domain::from).collect())
+                .collect::<Vec<Vec<Domain>>>(),
+        )
     }
 }
 
 impl Debug for PossibleVals {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (w, h) = self.topology.size();
         // Calculate the maximum length of the string representation of any value in each column
-        let mut max_lengths = self.inner[0].iter().map(|_| 0).collect::<Vec<_>>();
-        for row in self.inner.iter() {
-            for (tile, max_length) in row.iter().zip(max_lengths.iter_mut()) {
-                *max_length = (*max_length).max(format!("{:?}", tile).len());
-            }
+        let mut max_lengths = vec![0; h];
+        for (x, y) in self.coords() {
+            max_lengths[y] = max_lengths[y].max(format!("{:?}", self.get(x, y)).len());
         }
 
         writeln!(f)?;
-        for row in self.inner.iter() {
-            for (tile, &max_length) in row.iter().zip(max_lengths.iter()) {
+        for x in 0..w {
+            for (y, &max_length) in max_lengths.iter().enumerate() {
                 // Use the maximum length as the width specifier
-                let mut sorted = tile.iter().collect::<Vec<_>>();
-                sorted.sort_unstable();
-                let s = format!("{:width$}", format!("{:?}", sorted), width = max_length);
+                let s = format!(
+                    "{:width$}",
+                    format!("{:?}", self.get(x, y)),
+                    width = max_length
+                );
                 write!(f, "{} ", s)?;
             }
             writeln!(f)?;
@@ -66,83 +191,158 @@ impl Debug for PossibleVals {
     }
 }
 
+/// The possibilities of every cell, with the `dimensions` of their topology.
 #[derive(Clone, Debug)]
 pub struct State {
     pub possible_vals: PossibleVals,
     pub width: usize,
+    /// Rows of a single layer.
     pub height: usize,
-    pub curr_file_index: u32,
+    /// Number of layers, 1 for a 2D grid.
+    pub depth: usize,
 }
 
 impl State {
     pub fn new(w: usize, h: usize, all_tiles_types: &HashSet<Tile>) -> Self {
+        State::new_3d(w, h, 1, all_tiles_types)
+    }
+
+    /// A grid of `d` layers of `w` by `h` cells.
+    pub fn new_3d(w: usize, h: usize, d: usize, all_tiles_types: &HashSet<Tile>) -> Self {
+        let grid = Grid {
+            depth: d,
+            ..Grid::new(w, h)
+        };
+        State::with_topology(Arc::new(grid), all_tiles_types)
+    }
+
+    /// Every cell of `topology`, each one allowing all of `all_tiles_types`.
+    pub fn with_topology(topology: Arc<dyn Topology>, all_tiles_types: &HashSet<Tile>) -> Self {
+        State::from_possible_vals(PossibleVals::new(topology, Domain::from(all_tiles_types)))
+    }
+
+    fn from_possible_vals(possible_vals: PossibleVals) -> Self {
+        let (width, height, depth) = possible_vals.topology.dimensions();
         State {
-            possible_vals: PossibleVals {
-                inner: vec![vec![all_tiles_types.clone(); w]; h],
-            },
-            curr_file_index: 0,
-            width: w,
-            height: h,
-        }
-    }
-
-    pub fn save_into_file(&mut self, end: &str) {
-        let mut file_path = PathBuf::new();
-        file_path.push("imgs");
-        file_path.push("output");
-        file_path.push(&format!("state_{}_{}", self.curr_file_index, end));
-        file_path.set_extension("txt");
-        println!("Saving into file: {:?}", file_path);
-        if let Some(parent) = file_path.parent() {
-            std::fs::create_dir_all(parent).expect("Failed to create directories");
-        }
-        let file = std::fs::File::create(file_path).unwrap();
-        self.curr_file_index += 1;
-        let mut w = std::io::BufWriter::new(file);
-        let max_col = self.possible_vals.inner[0].len();
-        for col in 0..max_col {
-            for row in &self.possible_vals.inner {
-                if let Some(tile) = row.get(col) {
-                    for t in tile {
-                        let _ = write!(w, "{}", t.minify());
-                    }
-                    let _ = write!(w, "\t");
-                }
-            }
-            let _ = writeln!(w);
+            possible_vals,
+            width,
+            height,
+            depth,
         }
+    }
 
-        w.flush().expect("Should be able to flush writer buffer.");
+    pub fn get(&self, x: usize, y: usize) -> &Domain {
+        self.possible_vals.get(x, y)
     }
 
-    pub fn save_rules_into_file(&self, rules: &HashSet<Rule>) {
-        let mut file_path = PathBuf::new();
-        file_path.push("imgs");
-        file_path.push("output");
-        file_path.push("rules");
-        file_path.set_extension("txt");
-        println!("Saving rules into file: {:?}", file_path);
-        if let Some(parent) = file_path.parent() {
-            std::fs::create_dir_all(parent).expect("Failed to create directories");
-        }
-        let file = std::fs::File::create(file_path).unwrap();
-        let mut w = std::io::BufWriter::new(file);
-        for rule in rules {
-            let _ = writeln!(
-                w,
-                "{:?} can be at {:?} of {:?}",
-                rule.curr_tile, rule.direction, rule.adj_tile
-            );
-        }
-        w.flush().expect("Should be able to flush writer buffer.");
+    /// Like `get`, failing with `Error::OutOfBounds` instead of panicking.
+    pub fn try_get(&self, x: usize, y: usize) -> Result<&Domain> {
+        self.possible_vals.try_get(x, y)
     }
 
-    pub fn get(&self, x: usize, y: usize) -> HashSet<Tile> {
-        self.possible_vals.inner[x][y].clone()
+    /// The cell at (`x`, `y`) of layer `z`.
+    pub fn get_voxel(&self, x: usize, y: usize, z: usize) -> &Domain {
+        self.possible_vals.get(x, z * self.height + y)
+    }
+
+    /// Layer `z` on its own, as a 2D grid.
+    pub fn layer(&self, z: usize) -> State {
+        let grid = match self.possible_vals.topology.grid() {
+            Some(grid) => Grid { depth: 1, ..*grid },
+            None => Grid::new(self.width, self.height),
+        };
+        let mut possible_vals = PossibleVals::new(Arc::new(grid), Domain::new());
+        for (x, y) in possible_vals.coords() {
+            possible_vals.set(x, y, self.get_voxel(x, y, z).clone());
+        }
+        State::from_possible_vals(possible_vals)
     }
 
     pub fn get_total_entropy(&self) -> usize {
-        self.possible_vals.inner.iter().flatten().flatten().count()
+        self.possible_vals.cells().iter().map(Domain::len).sum()
+    }
+
+    /// Keeps only `tiles` in the cell at `(x, y)`. Fails with `Error::OutOfBounds`
+    /// outside the grid and with `Error::InvalidConstraint` when no tile is left.
+    ///
+    /// Nothing is propagated here: the solver checks the constraints against the rules
+    /// and propagates them before its first collapse.
+    pub fn restrict(&mut self, x: usize, y: usize, tiles: impl Into<Domain>) -> Result<()> {
+        self.restrict_rect(x, y, 1, 1, tiles)
+    }
+
+    /// Like `restrict`, for every cell of the `width` by `height` rectangle whose
+    /// top-left corner is `(x, y)`, in the first layer.
+    pub fn restrict_rect(
+        &mut self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        tiles: impl Into<Domain>,
+    ) -> Result<()> {
+        self.restrict_box(x, y, 0, width, height, 1, tiles)
+    }
+
+    /// Like `restrict`, for the cell at `(x, y)` of layer `z`.
+    pub fn restrict_voxel(
+        &mut self,
+        x: usize,
+        y: usize,
+        z: usize,
+        tiles: impl Into<Domain>,
+    ) -> Result<()> {
+        self.restrict_box(x, y, z, 1, 1, 1, tiles)
+    }
+
+    /// Like `restrict_rect`, for the same rectangle in `depth` layers from layer `z`.
+    /// The errors give the cell as `PossibleVals` addresses it, (`x`, `z * height + y`).
+    #[allow(clippy::too_many_arguments)]
+    pub fn restrict_box(
+        &mut self,
+        x: usize,
+        y: usize,
+        z: usize,
+        width: usize,
+        height: usize,
+        depth: usize,
+        tiles: impl Into<Domain>,
+    ) -> Result<()> {
+        if width == 0 || height == 0 || depth == 0 {
+            return Ok(());
+        }
+        if x + width > self.width || y + height > self.height || z + depth > self.depth {
+            // The far corner is always one of the cells outside.
+            return Err(Error::OutOfBounds {
+                x: x + width - 1,
+                y: (z + depth - 1) * self.height + y + height - 1,
+            });
+        }
+        let tiles = tiles.into();
+        for cz in z..z + depth {
+            for cx in x..x + width {
+                for cy in cz * self.height + y..cz * self.height + y + height {
+                    self.possible_vals.intersect(cx, cy, &tiles);
+                    if self.get(cx, cy).is_empty() {
+                        return Err(Error::InvalidConstraint { x: cx, y: cy });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails with `Error::InvalidDimensions` when the state has more than one layer,
+    /// which a single image cannot show.
+    pub(crate) fn check_single_layer(&self) -> Result<()> {
+        if self.depth > 1 {
+            return Err(Error::InvalidDimensions {
+                width: self.width as u32,
+                height: self.height as u32,
+                depth: self.depth as u32,
+            });
+        }
+        Ok(())
     }
 
     pub fn with_possibilities(&self, possible_vals: PossibleVals) -> Self {
@@ -153,69 +353,49 @@ impl State {
 }
 
 pub fn contains_invalid_tiles(possible_vals: &PossibleVals) -> bool {
-    for row in possible_vals.inner.iter() {
-        for tile in row {
-            if tile.is_empty() {
-                return true;
-            }
-        }
+    possible_vals.cells().iter().any(Domain::is_empty)
+}
+
+/// Turns a collapsed state into an image. A 3D state is drawn one layer at a time with
+/// `voxel::render_slices`.
+pub trait Render {
+    /// Fails with `Error::Unrenderable` when the state is not fully collapsed or
+    /// uses an unknown tile, and with `Error::InvalidDimensions` when it has more than
+    /// one layer.
+    fn render(&self, state: &State) -> Result<Image>;
+}
+
+impl Render for Palette {
+    fn render(&self, state: &State) -> Result<Image> {
+        get_image_from_possible_vals(state, self)
     }
-    false
 }
 
-pub fn get_image_from_possible_vals(state: &State) -> Option<Image> {
+pub fn is_collapsed(possible_vals: &PossibleVals) -> bool {
+    possible_vals.is_collapsed()
+}
+
+pub fn get_image_from_possible_vals(state: &State, palette: &Palette) -> Result<Image> {
+    state.check_single_layer()?;
     let w = state.width as u32;
     let h = state.height as u32;
     let mut img = Image::new(w, h);
     for x in 0..w {
         for y in 0..h {
-            if state.get(x as usize, y as usize).len() != 1 {
-                return None;
-            }
-            img.set_pixel(
-                x,
-                y,
-                state
-                    .get(x as usize, y as usize)
-                    .iter()
-                    .next()
-                    .unwrap()
-                    .clone()
-                    .into(),
-            );
+            let tile = state
+                .get(x as usize, y as usize)
+                .single()
+                .ok_or(Error::Unrenderable)?;
+            img.set_pixel(x, y, palette.pixel(&tile).ok_or(Error::Unrenderable)?);
         }
     }
 
-    Some(img)
+    Ok(img)
 }
 
-fn get_lowest_entropy_tile(possible_vals: &PossibleVals) -> Option<(usize, usize)> {
-    let mut min_entropy = usize::MAX;
-    let mut min_entropy_tiles = Vec::new();
-
-    for (i, row) in possible_vals.inner.iter().enumerate() {
-        for (j, tile) in row.iter().enumerate() {
-            let entropy = tile.len();
-
-            if entropy == 1 {
-                continue;
-            }
-
-            match entropy.cmp(&min_entropy) {
-                std::cmp::Ordering::Less => {
-                    min_entropy = entropy;
-                    min_entropy_tiles.clear();
-                    min_entropy_tiles.push((i, j));
-                }
-                std::cmp::Ordering::Equal => {
-                    min_entropy_tiles.push((i, j));
-                }
-                std::cmp::Ordering::Greater => {}
-            }
-        }
-    }
-
-    min_entropy_tiles.choose(&mut rand::thread_rng()).cloned()
+/// Shannon entropy of the weighted possibilities of a cell, `-Σ p log p`.
+pub fn shannon_entropy(possibilities: &Domain, weights: &Weights) -> f64 {
+    WeightSums::new(possibilities, weights).entropy()
 }
 
 pub trait HashSetExt<T> {
@@ -247,194 +427,648 @@ impl<T: std::hash::Hash + Eq + Clone> HashSetExt<T> for HashSet<T> {
 }
 
 pub fn print_tile_possibilities_and_adjacents(possible_vals: &PossibleVals, x: usize, y: usize) {
-    let (curr, up, down, left, right) = get_possibilities_adjacent_pixels(possible_vals, x, y);
-    println!("Current: {:?}", curr);
-    println!("Up: {:?}", up);
-    println!("Down: {:?}", down);
-    println!("Left: {:?}", left);
-    println!("Right: {:?}", right);
+    println!("Current: {:?}", possible_vals.get(x, y));
+    for (direction, possibilities) in get_possibilities_neighbours(possible_vals, x, y) {
+        println!("{:?}: {:?}", direction, possibilities);
+    }
+}
+
+/// A tile chosen for a cell, with the previous possibilities of every cell it changed.
+#[derive(Clone, Debug)]
+pub struct Decision {
+    pub coord: (usize, usize),
+    pub tile: Tile,
+    pub trail: Trail,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Backtrack {
+    /// The grid is consistent again once `tile` is banned from the cell at `coord`.
+    Resolved { coord: (usize, usize), tile: Tile },
+    /// Every decision has been revised: the rules cannot be satisfied.
+    Exhausted,
+    /// Resolving the contradiction needs to unwind more decisions than allowed.
+    TooDeep,
 }
 
-pub fn generate_image(w: u32, h: u32, rules: &HashSet<Rule>) -> Option<Image> {
-    delete_files_in_dir("imgs/output").expect("Failed to delete files");
-    let mut all_tiles_types = HashSet::new();
-    for rule in rules {
-        all_tiles_types.insert(rule.curr_tile.clone());
-        all_tiles_types.insert(rule.adj_tile.clone());
+/// Undoes the `failed` decision, bans its tile from its cell and propagates the ban.
+///
+/// The ban is recorded on the trail of the previous decision, so it is lifted if that
+/// decision is undone in turn. When the ban itself leads to a contradiction, the
+/// previous decision is popped from `stack` and banned the same way, unwinding at most
+/// `max_depth` decisions.
+pub fn backtrack(
+    possible_vals: &mut PossibleVals,
+    adjacency: &Adjacency,
+    stack: &mut Vec<Decision>,
+    root_trail: &mut Trail,
+    failed: Decision,
+    max_depth: Option<usize>,
+) -> Backtrack {
+    let mut failed = failed;
+    let mut depth = 0;
+    loop {
+        undo(possible_vals, &mut failed.trail);
+
+        let parent_trail = match stack.last_mut() {
+            Some(parent) => &mut parent.trail,
+            None => &mut *root_trail,
+        };
+        let (x, y) = failed.coord;
+        let mut remaining = possible_vals.get(x, y).clone();
+        parent_trail.push(((x, y), remaining.clone()));
+        remaining.remove(&failed.tile);
+        let banned = !remaining.is_empty();
+        possible_vals.set(x, y, remaining);
+        if banned && propagate(possible_vals, adjacency, vec![(x, y)], parent_trail) {
+            return Backtrack::Resolved {
+                coord: (x, y),
+                tile: failed.tile,
+            };
+        }
+
+        failed = match stack.pop() {
+            Some(parent) => parent,
+            None => return Backtrack::Exhausted,
+        };
+        depth += 1;
+        if max_depth.is_some_and(|max_depth| depth > max_depth) {
+            return Backtrack::TooDeep;
+        }
     }
+}
 
-    let mut state: State = State::new(w as usize, h as usize, &all_tiles_types);
-    state.save_rules_into_file(rules);
-    state.save_into_file("initial");
+/// Picks one of `possibilities` with a probability proportional to its weight.
+/// Falls back to a uniform choice when every remaining weight is zero.
+fn choose_tile(possibilities: &Domain, weights: &Weights, rng: &mut impl Rng) -> Option<Tile> {
+    let possibilities = possibilities.iter().collect::<Vec<_>>();
+    possibilities
+        .choose_weighted(rng, |tile| weights.get(tile))
+        .or_else(|_| possibilities.choose(rng).ok_or(()))
+        .ok()
+        .cloned()
+}
 
-    while state
-        .possible_vals
-        .inner
-        .iter()
-        .any(|row| row.iter().any(|tile| tile.len() > 1))
-    {
-        let mut old_state = state.clone();
-        let next_tile_coord = get_lowest_entropy_tile(&state.possible_vals);
-        if next_tile_coord.is_none() {
-            break;
-        }
-        let next_tile_coord = next_tile_coord.unwrap();
-        let next_tile_color = (*state.possible_vals.inner[next_tile_coord.0 as usize]
-            [next_tile_coord.1 as usize]
-            .iter()
-            .collect::<Vec<_>>()
-            .choose(&mut rand::thread_rng())
-            .unwrap())
-        .clone();
-        state.possible_vals.inner[next_tile_coord.0 as usize][next_tile_coord.1 as usize] =
-            HashSet::new().with(next_tile_color.clone());
-
-        apply_rules(&state, rules);
-        while contains_invalid_tiles(&state.possible_vals) {
-            state.possible_vals = old_state.possible_vals.clone();
-            let next_tile_coord = get_lowest_entropy_tile(&state.possible_vals);
-            if next_tile_coord.is_none() {
+/// Knobs of the solver shared by every attempt.
+#[derive(Clone, Debug, Default)]
+pub struct SolverOptions {
+    pub heuristic: Heuristic,
+    /// Most decisions unwound to resolve one contradiction, `None` for no limit.
+    pub max_backtrack_depth: Option<usize>,
+    pub restart: RestartPolicy,
+    /// Seed of the random generator, `None` to draw one from the OS. The same seed,
+    /// rules, weights and size always produce the same result.
+    pub seed: Option<u64>,
+    /// Wraps each layer around so the left and right edges, and the top and bottom
+    /// ones, are neighbours. The result can then be repeated seamlessly. A periodic
+    /// hex grid needs an even height for its rows to line up, `GeneratorBuilder::build`
+    /// fails with `Error::InvalidDimensions` on an odd one.
+    pub periodic: bool,
+    pub tiling: Tiling,
+}
+
+impl SolverOptions {
+    /// A `width` by `height` grid of `depth` layers, with the `periodic` and `tiling`
+    /// of the options.
+    pub fn grid(&self, width: usize, height: usize, depth: usize) -> Grid {
+        Grid {
+            width,
+            height,
+            depth,
+            periodic: self.periodic,
+            tiling: self.tiling,
+        }
+    }
+}
+
+pub fn generate_image(
+    w: u32,
+    h: u32,
+    rules: &HashSet<Rule>,
+    weights: &Weights,
+    options: &SolverOptions,
+    palette: &Palette,
+) -> Result<Image> {
+    let state = generate_state(w, h, rules, weights, options)?;
+    get_image_from_possible_vals(&state, palette)
+}
+
+/// Runs up to `options.restart.max_attempts` attempts, each one starting over from
+/// an empty grid once it exceeds its contradiction or backtracking budget.
+pub fn generate_state(
+    w: u32,
+    h: u32,
+    rules: &HashSet<Rule>,
+    weights: &Weights,
+    options: &SolverOptions,
+) -> Result<State> {
+    let mut rng = match options.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    generate_state_with_rng(w, h, rules, weights, options, &mut rng)
+}
+
+/// Like `generate_state`, drawing every random choice from `rng` instead of `options.seed`.
+pub fn generate_state_with_rng(
+    w: u32,
+    h: u32,
+    rules: &HashSet<Rule>,
+    weights: &Weights,
+    options: &SolverOptions,
+    rng: &mut impl Rng,
+) -> Result<State> {
+    let adjacency = Adjacency::new(rules);
+    Solver::new(
+        Arc::new(options.grid(w as usize, h as usize, 1)),
+        rules,
+        &adjacency,
+        weights,
+        options,
+        rng,
+        NoObserver,
+    )?
+    .finish()
+}
+
+/// A generation that advances one step at a time, so it can be paused, inspected and
+/// resumed. A step selects a cell, collapses it and propagates, then backtracks or
+/// restarts on a contradiction.
+pub struct Solver<'a, R: Rng, O: Observer> {
+    adjacency: &'a Adjacency,
+    weights: &'a Weights,
+    options: &'a SolverOptions,
+    rng: R,
+    observer: O,
+    /// The grid every attempt starts from, after the initial propagation.
+    initial: State,
+    state: State,
+    stack: Vec<Decision>,
+    root_trail: Trail,
+    attempt: usize,
+    /// Contradictions of the current attempt and of the previous ones.
+    contradictions: usize,
+    past_contradictions: usize,
+    steps: usize,
+    done: bool,
+}
+
+impl<'a, R: Rng, O: Observer> Solver<'a, R, O> {
+    /// Lays the cells out along `topology` and runs the initial propagation. Fails
+    /// when the size is zero, when there are no rules or when the rules cannot be
+    /// satisfied at all.
+    pub(crate) fn new(
+        topology: Arc<dyn Topology>,
+        rules: &HashSet<Rule>,
+        adjacency: &'a Adjacency,
+        weights: &'a Weights,
+        options: &'a SolverOptions,
+        rng: R,
+        observer: O,
+    ) -> Result<Self> {
+        let initial = initial_state(topology, rules, adjacency, options);
+        Self::start(initial, rules, adjacency, weights, options, rng, observer)
+    }
+
+    /// Like `new`, starting from the cells `constrained` restricts, e.g. with
+    /// `State::restrict`. `constrained` must have the size of `topology`. Fails with
+    /// `Error::InvalidConstraint` when a cell allows no tile of the rules and with
+    /// `Error::Unsatisfiable` when the constraints contradict each other.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn from_state(
+        topology: Arc<dyn Topology>,
+        constrained: &State,
+        rules: &HashSet<Rule>,
+        adjacency: &'a Adjacency,
+        weights: &'a Weights,
+        options: &'a SolverOptions,
+        rng: R,
+        observer: O,
+    ) -> Result<Self> {
+        let initial = constrained_state(topology, constrained, rules, adjacency, options);
+        Self::start(initial, rules, adjacency, weights, options, rng, observer)
+    }
+
+    fn start(
+        initial: Result<State>,
+        rules: &HashSet<Rule>,
+        adjacency: &'a Adjacency,
+        weights: &'a Weights,
+        options: &'a SolverOptions,
+        mut rng: R,
+        mut observer: O,
+    ) -> Result<Self> {
+        let initial = match initial {
+            Ok(initial) => initial,
+            Err(err) => {
+                observer.on_finished(Err(&err))?;
+                return Err(err);
+            }
+        };
+        observer.on_start(rules, &initial)?;
+        observer.on_restart(0, &initial)?;
+        let mut state = initial.clone();
+        state
+            .possible_vals
+            .rank(&options.heuristic, weights, &mut rng);
+        Ok(Solver {
+            adjacency,
+            weights,
+            options,
+            rng,
+            observer,
+            state,
+            initial,
+            stack: Vec::new(),
+            root_trail: Vec::new(),
+            attempt: 0,
+            contradictions: 0,
+            past_contradictions: 0,
+            steps: 0,
+            done: false,
+        })
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub fn possible_vals(&self) -> &PossibleVals {
+        &self.state.possible_vals
+    }
+
+    pub fn observer(&self) -> &O {
+        &self.observer
+    }
+
+    /// The current attempt, starting at 0.
+    pub fn attempt(&self) -> usize {
+        self.attempt
+    }
+
+    /// Steps taken so far, over every attempt.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Whether the grid is fully collapsed or the generation failed.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Collapses one cell and propagates. The error that stops the generation is
+    /// returned once; after that the solver is done and `step` does nothing.
+    pub fn step(&mut self) -> Result<()> {
+        if self.done {
+            return Ok(());
+        }
+        self.steps += 1;
+        match self.try_step() {
+            Ok(false) => Ok(()),
+            Ok(true) => {
+                self.done = true;
+                self.observer.on_finished(Ok(&self.state))
+            }
+            Err(err) => {
+                self.done = true;
+                self.observer.on_finished(Err(&err))?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Takes up to `n_steps` steps, stopping early once done.
+    pub fn run_until(&mut self, n_steps: usize) -> Result<()> {
+        for _ in 0..n_steps {
+            if self.done {
                 break;
             }
-            let next_tile_coord = next_tile_coord.unwrap();
-            let next_tile_color = (*state.possible_vals.inner[next_tile_coord.0 as usize]
-                [next_tile_coord.1 as usize]
-                .iter()
-                .collect::<Vec<_>>()
-                .choose(&mut rand::thread_rng())
-                .unwrap())
-            .clone();
-            state.possible_vals.inner[next_tile_coord.0 as usize][next_tile_coord.1 as usize] =
-                HashSet::new().with(next_tile_color.clone());
-            apply_rules(&state, rules);
-            state.save_into_file("after_rule");
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    /// Steps until done and returns the collapsed grid.
+    pub fn finish(mut self) -> Result<State> {
+        while !self.done {
+            self.step()?;
+        }
+        if !is_collapsed(&self.state.possible_vals) {
+            return Err(Error::Unsatisfiable);
         }
+        Ok(self.state)
+    }
 
-        while old_state.get_total_entropy() != state.get_total_entropy() {
-            old_state = state.clone();
-            apply_rules(&state, rules);
+    /// Returns whether the grid is fully collapsed.
+    fn try_step(&mut self) -> Result<bool> {
+        let Some(coord) = self.state.possible_vals.lowest() else {
+            if !is_collapsed(&self.state.possible_vals) {
+                return Err(Error::Unsatisfiable);
+            }
+            return Ok(true);
+        };
+        let domain = self.state.get(coord.0, coord.1);
+        self.observer.on_cell_selected(coord, domain)?;
+        let tile = choose_tile(domain, self.weights, &mut self.rng).ok_or(Error::Unsatisfiable)?;
+        self.observer.on_collapse(coord, &tile)?;
+
+        let mut trail = vec![(coord, self.state.get(coord.0, coord.1).clone())];
+        self.state
+            .possible_vals
+            .set(coord.0, coord.1, Domain::from_iter([tile.clone()]));
+        let consistent = propagate(
+            &mut self.state.possible_vals,
+            self.adjacency,
+            vec![coord],
+            &mut trail,
+        );
+        // The first entry is the collapsed cell itself.
+        report_reductions(&self.state.possible_vals, &trail[1..], &mut self.observer)?;
+        let decision = Decision { coord, tile, trail };
+        if consistent {
+            self.stack.push(decision);
+            return Ok(is_collapsed(&self.state.possible_vals));
+        }
+
+        self.contradictions += 1;
+        self.observer.on_contradiction(coord, &self.state)?;
+        let budget = self.options.restart.contradiction_budget(self.attempt);
+        if budget.is_some_and(|max| self.contradictions > max) {
+            self.restart()?;
+            return Ok(false);
         }
+        match backtrack(
+            &mut self.state.possible_vals,
+            self.adjacency,
+            &mut self.stack,
+            &mut self.root_trail,
+            decision,
+            self.options.max_backtrack_depth,
+        ) {
+            Backtrack::Resolved { coord, tile } => {
+                self.observer.on_backtrack(coord, &tile, &self.state)?
+            }
+            Backtrack::Exhausted => return Err(Error::Unsatisfiable),
+            Backtrack::TooDeep => {
+                self.restart()?;
+                return Ok(false);
+            }
+        }
+        // Removing the failed tile can leave the last open cell with a single one.
+        Ok(is_collapsed(&self.state.possible_vals))
     }
 
-    get_image_from_possible_vals(&state)
+    /// Starts the next attempt over from the initial grid, or fails once every
+    /// attempt ran out of its budget.
+    fn restart(&mut self) -> Result<()> {
+        self.past_contradictions += self.contradictions;
+        self.contradictions = 0;
+        self.attempt += 1;
+        if self.attempt >= self.options.restart.max_attempts {
+            return Err(Error::BudgetExhausted {
+                attempts: self.options.restart.max_attempts,
+                contradictions: self.past_contradictions,
+            });
+        }
+        self.state = self.initial.clone();
+        self.state
+            .possible_vals
+            .rank(&self.options.heuristic, self.weights, &mut self.rng);
+        self.stack.clear();
+        self.root_trail.clear();
+        self.observer.on_restart(self.attempt, &self.state)
+    }
+}
+
+/// The empty grid, without the tiles that can never fit, e.g. tiles only seen on
+/// the sample's border.
+pub(crate) fn initial_state(
+    topology: Arc<dyn Topology>,
+    rules: &HashSet<Rule>,
+    adjacency: &Adjacency,
+    options: &SolverOptions,
+) -> Result<State> {
+    let (w, h, d) = topology.dimensions();
+    if w == 0 || h == 0 || d == 0 {
+        return Err(Error::InvalidDimensions {
+            width: w as u32,
+            height: h as u32,
+            depth: d as u32,
+        });
+    }
+    if rules.is_empty() {
+        return Err(Error::EmptyRules);
+    }
+    if options.restart.max_attempts == 0 {
+        return Err(Error::BudgetExhausted {
+            attempts: 0,
+            contradictions: 0,
+        });
+    }
+    let all_tiles_types = adjacency.tiles();
+    let state = State::from_possible_vals(PossibleVals::new(topology, all_tiles_types.clone()));
+    // Drop the impossible tiles in one sweep, then keep propagating from the cells
+    // that sweep reduced.
+    let mut state = apply_adjacency(&state, adjacency)?;
+    let reduced = state
+        .possible_vals
+        .coords()
+        .filter(|&(x, y)| state.get(x, y).len() != all_tiles_types.len())
+        .collect();
+    if !propagate(
+        &mut state.possible_vals,
+        adjacency,
+        reduced,
+        &mut Vec::new(),
+    ) {
+        return Err(Error::Unsatisfiable);
+    }
+    Ok(state)
+}
+
+/// The initial grid restricted to the cells of `constrained`, propagated.
+fn constrained_state(
+    topology: Arc<dyn Topology>,
+    constrained: &State,
+    rules: &HashSet<Rule>,
+    adjacency: &Adjacency,
+    options: &SolverOptions,
+) -> Result<State> {
+    let mut state = initial_state(topology, rules, adjacency, options)?;
+    let mut changed = Vec::new();
+    for (x, y) in constrained.possible_vals.coords() {
+        let reduced = state
+            .possible_vals
+            .intersect(x, y, constrained.get(x, y))
+            .is_some();
+        if state.get(x, y).is_empty() {
+            return Err(Error::InvalidConstraint { x, y });
+        }
+        if reduced {
+            changed.push((x, y));
+        }
+    }
+    if !propagate(
+        &mut state.possible_vals,
+        adjacency,
+        changed,
+        &mut Vec::new(),
+    ) {
+        return Err(Error::Unsatisfiable);
+    }
+    Ok(state)
+}
+
+/// Reports every cell of `trail` once, with the oldest possibilities recorded for it.
+fn report_reductions(
+    possible_vals: &PossibleVals,
+    trail: &[((usize, usize), Domain)],
+    observer: &mut impl Observer,
+) -> Result<()> {
+    let mut reported = HashSet::new();
+    for ((x, y), before) in trail {
+        if reported.insert((*x, *y)) {
+            observer.on_domain_reduced((*x, *y), before, possible_vals.get(*x, *y))?;
+        }
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
 
-    mod get_lowest_entropy_tile {
+    mod lowest {
         use rstest::{fixture, rstest};
         use std::collections::HashSet;
 
         use crate::{
-            enums::Tile,
-            state::{get_lowest_entropy_tile, HashSetExt, PossibleVals},
+            domain::Domain,
+            enums::{Heuristic, Tile},
+            state::{HashSetExt, PossibleVals},
+            weights::Weights,
         };
 
+        fn lowest(mut possible_vals: PossibleVals) -> Option<(usize, usize)> {
+            possible_vals.rank(&Heuristic::Count, &Weights::new(), &mut rand::thread_rng());
+            possible_vals.lowest()
+        }
+
         #[fixture]
         fn one_at_2() -> PossibleVals {
-            PossibleVals {
-                inner: vec![
-                    vec![
-                        HashSet::new().with(Tile::Blue).with(Tile::Green),
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                    ],
-                    vec![
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                    ],
+            PossibleVals::from(vec![
+                vec![
+                    HashSet::new().with(Tile::BLUE).with(Tile::GREEN),
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
                 ],
-            }
+                vec![
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
+                ],
+            ])
         }
 
         #[fixture]
         fn one_at_2_and_1() -> PossibleVals {
-            PossibleVals {
-                inner: vec![
-                    vec![
-                        HashSet::new().with(Tile::Blue),
-                        HashSet::new().with(Tile::Blue).with(Tile::Green),
-                    ],
-                    vec![
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                    ],
+            PossibleVals::from(vec![
+                vec![
+                    HashSet::new().with(Tile::BLUE),
+                    HashSet::new().with(Tile::BLUE).with(Tile::GREEN),
                 ],
-            }
+                vec![
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
+                ],
+            ])
         }
 
         #[fixture]
         fn one_at_1() -> PossibleVals {
-            PossibleVals {
-                inner: vec![
-                    vec![
-                        HashSet::new().with(Tile::Blue),
-                        HashSet::new().with(Tile::Blue).with(Tile::Green),
-                    ],
-                    vec![
-                        HashSet::new().with(Tile::Blue).with(Tile::Green),
-                        HashSet::new().with(Tile::Blue).with(Tile::Green),
-                    ],
+            PossibleVals::from(vec![
+                vec![
+                    HashSet::new().with(Tile::BLUE),
+                    HashSet::new().with(Tile::BLUE).with(Tile::GREEN),
                 ],
-            }
+                vec![
+                    HashSet::new().with(Tile::BLUE).with(Tile::GREEN),
+                    HashSet::new().with(Tile::BLUE).with(Tile::GREEN),
+                ],
+            ])
         }
 
         #[fixture]
         fn all_at_1() -> PossibleVals {
-            PossibleVals {
-                inner: vec![
-                    vec![
-                        HashSet::new().with(Tile::Blue),
-                        HashSet::new().with(Tile::Green),
-                    ],
-                    vec![
-                        HashSet::new().with(Tile::Red),
-                        HashSet::new().with(Tile::Blue),
-                    ],
+            PossibleVals::from(vec![
+                vec![
+                    HashSet::new().with(Tile::BLUE),
+                    HashSet::new().with(Tile::GREEN),
                 ],
-            }
+                vec![
+                    HashSet::new().with(Tile::RED),
+                    HashSet::new().with(Tile::BLUE),
+                ],
+            ])
         }
 
         #[rstest]
         pub fn test1(one_at_2: PossibleVals) {
-            println!("{:?}", one_at_2.inner[0][0]);
-            let res = get_lowest_entropy_tile(&one_at_2);
-            assert_eq!(res, Some((0, 0)));
+            assert_eq!(lowest(one_at_2), Some((0, 0)));
         }
 
         #[rstest]
         pub fn test2(one_at_2_and_1: PossibleVals) {
-            let res = get_lowest_entropy_tile(&one_at_2_and_1);
-            assert_eq!(res, Some((0, 1)));
+            assert_eq!(lowest(one_at_2_and_1), Some((0, 1)));
         }
 
         #[rstest]
         pub fn test3(one_at_1: PossibleVals) {
             for _ in 0..1000 {
-                let res = get_lowest_entropy_tile(&one_at_1);
-                assert_ne!(res, Some((0, 0)));
+                assert_ne!(lowest(one_at_1.clone()), Some((0, 0)));
             }
         }
 
         #[rstest]
         pub fn test4(all_at_1: PossibleVals) {
-            let res = get_lowest_entropy_tile(&all_at_1);
-            assert_eq!(res, None);
+            assert!(all_at_1.is_collapsed());
+            assert_eq!(lowest(all_at_1), None);
+        }
+
+        #[rstest]
+        pub fn test_follows_changes(mut one_at_2: PossibleVals) {
+            one_at_2.rank(&Heuristic::Count, &Weights::new(), &mut rand::thread_rng());
+            one_at_2.set(1, 1, HashSet::new().with(Tile::RED).with(Tile::BLUE));
+            one_at_2.set(0, 0, HashSet::new().with(Tile::GREEN));
+            assert_eq!(one_at_2.lowest(), Some((1, 1)));
+
+            for (x, y) in [(0, 1), (1, 0), (1, 1)] {
+                assert!(!one_at_2.is_collapsed());
+                one_at_2.set(x, y, HashSet::new().with(Tile::RED));
+            }
+            assert!(one_at_2.is_collapsed());
+            assert_eq!(one_at_2.lowest(), None);
+
+            // Growing back reopens the cell.
+            let before = one_at_2.intersect(0, 0, &Domain::new()).unwrap();
+            assert!(!one_at_2.is_collapsed());
+            assert_eq!(one_at_2.lowest(), None);
+            one_at_2.set(0, 0, HashSet::new().with(Tile::RED).with(Tile::GREEN));
+            assert_eq!(one_at_2.lowest(), Some((0, 0)));
+            one_at_2.set(0, 0, before);
+            assert!(one_at_2.is_collapsed());
         }
     }
 
@@ -449,80 +1083,553 @@ mod tests {
 
         #[fixture]
         fn all_ok() -> PossibleVals {
-            PossibleVals {
-                inner: vec![
-                    vec![
-                        HashSet::new().with(Tile::Blue).with(Tile::Green),
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                    ],
-                    vec![
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                    ],
+            PossibleVals::from(vec![
+                vec![
+                    HashSet::new().with(Tile::BLUE).with(Tile::GREEN),
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
                 ],
-            }
+                vec![
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
+                ],
+            ])
         }
 
         #[fixture]
         fn one_not_ok() -> PossibleVals {
-            PossibleVals {
-                inner: vec![
-                    vec![
-                        HashSet::new(),
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                    ],
-                    vec![
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                        HashSet::new()
-                            .with(Tile::Blue)
-                            .with(Tile::Green)
-                            .with(Tile::Red),
-                    ],
+            PossibleVals::from(vec![
+                vec![
+                    HashSet::new(),
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
                 ],
-            }
+                vec![
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
+                    HashSet::new()
+                        .with(Tile::BLUE)
+                        .with(Tile::GREEN)
+                        .with(Tile::RED),
+                ],
+            ])
         }
 
         #[fixture]
         fn all_not_ok() -> PossibleVals {
-            PossibleVals {
-                inner: vec![
-                    vec![HashSet::new(), HashSet::new()],
-                    vec![HashSet::new(), HashSet::new()],
-                ],
-            }
+            PossibleVals::from(vec![
+                vec![HashSet::new(), HashSet::new()],
+                vec![HashSet::new(), HashSet::new()],
+            ])
         }
 
         #[rstest]
         pub fn test1(all_ok: PossibleVals) {
             let res = contains_invalid_tiles(&all_ok);
-            assert_eq!(res, false);
+            assert!(!res);
         }
 
         #[rstest]
         pub fn test2(one_not_ok: PossibleVals) {
             let res = contains_invalid_tiles(&one_not_ok);
-            assert_eq!(res, true);
+            assert!(res);
         }
 
         #[rstest]
         pub fn test3(all_not_ok: PossibleVals) {
             let res = contains_invalid_tiles(&all_not_ok);
-            assert_eq!(res, true);
+            assert!(res);
+        }
+    }
+
+    mod get_image_from_possible_vals {
+        use bmp::Pixel;
+        use std::collections::HashSet;
+
+        use crate::{
+            enums::Tile,
+            error::Error,
+            palette::Palette,
+            state::{get_image_from_possible_vals, HashSetExt, State},
+        };
+
+        #[test]
+        pub fn test_non_square_custom_colors() {
+            let mut palette = Palette::new();
+            palette.insert(Pixel::new(10, 20, 30));
+            palette.insert(Pixel::new(200, 100, 50));
+
+            let mut state = State::new(3, 2, &HashSet::from_all(vec![Tile(0), Tile(1)]));
+            for x in 0..3 {
+                for y in 0..2 {
+                    let tile = if x == y { Tile(1) } else { Tile(0) };
+                    state.possible_vals.set(x, y, HashSet::new().with(tile));
+                }
+            }
+
+            let img = get_image_from_possible_vals(&state, &palette).unwrap();
+            assert_eq!(img.get_width(), 3);
+            assert_eq!(img.get_height(), 2);
+            assert_eq!(img.get_pixel(0, 0), Pixel::new(200, 100, 50));
+            assert_eq!(img.get_pixel(1, 1), Pixel::new(200, 100, 50));
+            assert_eq!(img.get_pixel(2, 0), Pixel::new(10, 20, 30));
+            assert_eq!(img.get_pixel(0, 1), Pixel::new(10, 20, 30));
+        }
+
+        #[test]
+        pub fn test_not_collapsed() {
+            let mut palette = Palette::new();
+            palette.insert(Pixel::new(10, 20, 30));
+            palette.insert(Pixel::new(200, 100, 50));
+
+            let state = State::new(2, 2, &HashSet::from_all(vec![Tile(0), Tile(1)]));
+            assert!(matches!(
+                get_image_from_possible_vals(&state, &palette),
+                Err(Error::Unrenderable)
+            ));
+        }
+    }
+
+    mod choose_tile {
+        use std::collections::HashSet;
+
+        use crate::{
+            domain::Domain,
+            enums::Tile,
+            state::{choose_tile, HashSetExt},
+            weights::Weights,
+        };
+
+        #[test]
+        pub fn test_zero_weight_never_chosen() {
+            let possibilities =
+                Domain::from(HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE]));
+            let weights = Weights::new().with(Tile::RED, 0.0).with(Tile::BLUE, 10.0);
+            for _ in 0..1000 {
+                assert_ne!(
+                    choose_tile(&possibilities, &weights, &mut rand::thread_rng()),
+                    Some(Tile::RED)
+                );
+            }
+        }
+
+        #[test]
+        pub fn test_all_zero_weights() {
+            let possibilities = Domain::from(HashSet::from_all(vec![Tile::RED, Tile::GREEN]));
+            let weights = Weights::new().with(Tile::RED, 0.0).with(Tile::GREEN, 0.0);
+            assert!(choose_tile(&possibilities, &weights, &mut rand::thread_rng()).is_some());
+            assert!(choose_tile(&Domain::new(), &weights, &mut rand::thread_rng()).is_none());
+        }
+    }
+
+    mod lowest_shannon_entropy {
+        use std::collections::HashSet;
+
+        use crate::{
+            domain::Domain,
+            enums::{Heuristic, Tile},
+            state::{shannon_entropy, HashSetExt, PossibleVals},
+            weights::Weights,
+        };
+
+        fn lowest(mut possible_vals: PossibleVals, weights: &Weights) -> Option<(usize, usize)> {
+            possible_vals.rank(&Heuristic::Entropy, weights, &mut rand::thread_rng());
+            possible_vals.lowest()
+        }
+
+        #[test]
+        pub fn test_shannon_entropy() {
+            let weights = Weights::new();
+            let one = Domain::from(HashSet::from_all(vec![Tile::RED]));
+            let two = Domain::from(HashSet::from_all(vec![Tile::RED, Tile::GREEN]));
+            assert_eq!(shannon_entropy(&one, &weights), 0.0);
+            assert!((shannon_entropy(&two, &weights) - 2f64.ln()).abs() < 1e-9);
+
+            let skewed = Weights::new().with(Tile::RED, 99.0);
+            assert!(shannon_entropy(&two, &skewed) < shannon_entropy(&two, &weights));
+        }
+
+        #[test]
+        pub fn test_prefers_skewed_weights() {
+            // Both undecided cells have two options, but (1, 0) is nearly decided by weight.
+            let possible_vals = PossibleVals::from(vec![
+                vec![
+                    HashSet::from_all(vec![Tile::GREEN, Tile::BLUE]),
+                    HashSet::from_all(vec![Tile::RED]),
+                ],
+                vec![
+                    HashSet::from_all(vec![Tile::RED, Tile::GREEN]),
+                    HashSet::from_all(vec![Tile::RED]),
+                ],
+            ]);
+            let weights = Weights::new().with(Tile::RED, 99.0);
+            for _ in 0..100 {
+                assert_eq!(lowest(possible_vals.clone(), &weights), Some((1, 0)));
+            }
+        }
+
+        #[test]
+        pub fn test_all_collapsed() {
+            let possible_vals = PossibleVals::from(vec![vec![
+                HashSet::from_all(vec![Tile::RED]),
+                HashSet::from_all(vec![Tile::GREEN]),
+            ]]);
+            assert_eq!(lowest(possible_vals, &Weights::new()), None);
+        }
+    }
+
+    mod backtrack {
+        use rstest::{fixture, rstest};
+        use std::collections::HashSet;
+
+        use crate::{
+            enums::{Direction, Tile},
+            rules::{propagate, Adjacency, Rule},
+            state::{backtrack, Backtrack, Decision, HashSetExt, PossibleVals},
+        };
+
+        /// Red, green and blue side by side must all differ.
+        #[fixture]
+        fn all_different_lr() -> Adjacency {
+            let tiles = [Tile::RED, Tile::GREEN, Tile::BLUE];
+            let mut rules = HashSet::new();
+            for curr in tiles.iter() {
+                for adj in tiles.iter().filter(|adj| *adj != curr) {
+                    rules.insert(Rule::new(curr.clone(), adj.clone(), Direction::Left));
+                    rules.insert(Rule::new(curr.clone(), adj.clone(), Direction::Right));
+                }
+            }
+            Adjacency::new(&rules)
+        }
+
+        fn decide(
+            possible_vals: &mut PossibleVals,
+            adjacency: &Adjacency,
+            coord: (usize, usize),
+            tile: Tile,
+        ) -> (Decision, bool) {
+            let mut trail = vec![(coord, possible_vals.get(coord.0, coord.1).clone())];
+            possible_vals.set(coord.0, coord.1, HashSet::new().with(tile.clone()));
+            let consistent = propagate(possible_vals, adjacency, vec![coord], &mut trail);
+            (Decision { coord, tile, trail }, consistent)
+        }
+
+        #[test]
+        fn test_unsatisfiable() {
+            // Green must be below red, yet red can only be above red.
+            let adjacency = Adjacency::new(&HashSet::from_all(vec![
+                Rule::new(Tile::GREEN, Tile::RED, Direction::Down),
+                Rule::new(Tile::RED, Tile::GREEN, Direction::Down),
+                Rule::new(Tile::RED, Tile::RED, Direction::Up),
+                Rule::new(Tile::GREEN, Tile::GREEN, Direction::Up),
+            ]));
+            let mut possible_vals =
+                PossibleVals::from(vec![vec![
+                    HashSet::from_all(vec![Tile::RED, Tile::GREEN]);
+                    2
+                ]]);
+
+            let (decision, consistent) = decide(&mut possible_vals, &adjacency, (0, 0), Tile::RED);
+            assert!(!consistent);
+            let mut stack = Vec::new();
+            let mut root_trail = Vec::new();
+            assert_eq!(
+                backtrack(
+                    &mut possible_vals,
+                    &adjacency,
+                    &mut stack,
+                    &mut root_trail,
+                    decision,
+                    None
+                ),
+                Backtrack::Exhausted
+            );
+        }
+
+        #[rstest]
+        fn test_ban_failed_tile(all_different_lr: Adjacency) {
+            let mut possible_vals = PossibleVals::from(vec![
+                vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE])],
+                vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE])],
+                vec![HashSet::from_all(vec![Tile::RED])],
+            ]);
+
+            let (first, consistent) =
+                decide(&mut possible_vals, &all_different_lr, (0, 0), Tile::GREEN);
+            assert!(consistent);
+            let mut stack = vec![first];
+            let (second, consistent) =
+                decide(&mut possible_vals, &all_different_lr, (1, 0), Tile::RED);
+            assert!(!consistent);
+
+            let mut root_trail = Vec::new();
+            assert_eq!(
+                backtrack(
+                    &mut possible_vals,
+                    &all_different_lr,
+                    &mut stack,
+                    &mut root_trail,
+                    second,
+                    None
+                ),
+                Backtrack::Resolved {
+                    coord: (1, 0),
+                    tile: Tile::RED
+                }
+            );
+            assert_eq!(stack.len(), 1);
+            assert_eq!(possible_vals.get(1, 0), HashSet::from_all(vec![Tile::BLUE]));
+
+            // Undoing the first decision also lifts the ban.
+            let mut first = stack.pop().unwrap();
+            crate::rules::undo(&mut possible_vals, &mut first.trail);
+            assert_eq!(
+                possible_vals.get(1, 0),
+                HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE])
+            );
+        }
+
+        #[rstest]
+        fn test_unwind_previous_decision(all_different_lr: Adjacency) {
+            let mut possible_vals = PossibleVals::from(vec![
+                vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN])],
+                vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE])],
+                vec![HashSet::from_all(vec![Tile::BLUE, Tile::GREEN])],
+            ]);
+
+            // With red on the left and blue on the right, the middle cell can only be
+            // green: banning green from it unwinds the blue decision, which is banned instead.
+            let (first, consistent) =
+                decide(&mut possible_vals, &all_different_lr, (0, 0), Tile::RED);
+            assert!(consistent);
+            let mut stack = vec![first];
+            let (second, consistent) =
+                decide(&mut possible_vals, &all_different_lr, (2, 0), Tile::BLUE);
+            assert!(consistent);
+            stack.push(second);
+            let (third, consistent) =
+                decide(&mut possible_vals, &all_different_lr, (1, 0), Tile::GREEN);
+            assert!(consistent);
+            let mut root_trail = Vec::new();
+            assert_eq!(
+                backtrack(
+                    &mut possible_vals,
+                    &all_different_lr,
+                    &mut stack,
+                    &mut root_trail,
+                    third,
+                    None
+                ),
+                Backtrack::Resolved {
+                    coord: (2, 0),
+                    tile: Tile::BLUE
+                }
+            );
+            assert_eq!(stack.len(), 1);
+            assert_eq!(
+                possible_vals.get(2, 0),
+                HashSet::from_all(vec![Tile::GREEN])
+            );
+        }
+
+        #[rstest]
+        fn test_max_depth(all_different_lr: Adjacency) {
+            let mut possible_vals = PossibleVals::from(vec![
+                vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN])],
+                vec![HashSet::from_all(vec![Tile::RED, Tile::GREEN, Tile::BLUE])],
+                vec![HashSet::from_all(vec![Tile::BLUE, Tile::GREEN])],
+            ]);
+
+            let (first, _) = decide(&mut possible_vals, &all_different_lr, (0, 0), Tile::RED);
+            let (second, _) = decide(&mut possible_vals, &all_different_lr, (2, 0), Tile::BLUE);
+            let mut stack = vec![first, second];
+            let (third, _) = decide(&mut possible_vals, &all_different_lr, (1, 0), Tile::GREEN);
+            let mut root_trail = Vec::new();
+            assert_eq!(
+                backtrack(
+                    &mut possible_vals,
+                    &all_different_lr,
+                    &mut stack,
+                    &mut root_trail,
+                    third,
+                    Some(0)
+                ),
+                Backtrack::TooDeep
+            );
+        }
+    }
+
+    mod try_get {
+        use std::collections::HashSet;
+
+        use crate::{
+            enums::Tile,
+            error::Error,
+            state::{HashSetExt, State},
+        };
+
+        #[test]
+        pub fn test_try_get() {
+            let state = State::new(3, 2, &HashSet::from_all(vec![Tile::RED]));
+            assert_eq!(state.try_get(2, 1).unwrap(), state.get(2, 1));
+            assert!(matches!(
+                state.try_get(3, 0),
+                Err(Error::OutOfBounds { x: 3, y: 0 })
+            ));
+            assert!(matches!(
+                state.possible_vals.try_get(0, 2),
+                Err(Error::OutOfBounds { x: 0, y: 2 })
+            ));
+        }
+    }
+
+    mod restrict {
+        use std::collections::HashSet;
+
+        use crate::{
+            enums::Tile,
+            error::Error,
+            state::{HashSetExt, State},
+        };
+
+        #[test]
+        pub fn test_restrict_rect() {
+            let mut state = State::new(3, 3, &HashSet::from_all(vec![Tile::RED, Tile::BLUE]));
+            state
+                .restrict_rect(1, 1, 2, 2, HashSet::from_all(vec![Tile::BLUE, Tile::GREEN]))
+                .unwrap();
+            assert_eq!(state.get(0, 0).len(), 2);
+            assert_eq!(state.get(1, 0).len(), 2);
+            assert_eq!(state.get(1, 1), HashSet::from_all(vec![Tile::BLUE]));
+            assert_eq!(state.get(2, 2), HashSet::from_all(vec![Tile::BLUE]));
+
+            assert!(matches!(
+                state.restrict_rect(2, 0, 2, 1, HashSet::from_all(vec![Tile::RED])),
+                Err(Error::OutOfBounds { x: 3, y: 0 })
+            ));
+            assert!(matches!(
+                state.restrict(2, 1, HashSet::from_all(vec![Tile::RED])),
+                Err(Error::InvalidConstraint { x: 2, y: 1 })
+            ));
+        }
+
+        #[test]
+        pub fn test_restrict_box() {
+            let tiles = HashSet::from_all(vec![Tile::RED, Tile::BLUE]);
+            let mut state = State::new_3d(2, 2, 3, &tiles);
+            state
+                .restrict_box(0, 1, 1, 2, 1, 2, HashSet::from_all(vec![Tile::BLUE]))
+                .unwrap();
+            for x in 0..2 {
+                assert_eq!(state.get_voxel(x, 0, 1).len(), 2);
+                assert_eq!(state.get_voxel(x, 1, 0).len(), 2);
+                assert_eq!(
+                    state.get_voxel(x, 1, 1),
+                    HashSet::from_all(vec![Tile::BLUE])
+                );
+                assert_eq!(
+                    state.get_voxel(x, 1, 2),
+                    HashSet::from_all(vec![Tile::BLUE])
+                );
+            }
+
+            state
+                .restrict_voxel(1, 0, 2, HashSet::from_all(vec![Tile::RED]))
+                .unwrap();
+            assert_eq!(state.get(1, 4), HashSet::from_all(vec![Tile::RED]));
+            assert_eq!(state.get_voxel(1, 0, 0).len(), 2);
+
+            assert!(matches!(
+                state.restrict_voxel(0, 0, 3, tiles.clone()),
+                Err(Error::OutOfBounds { x: 0, y: 6 })
+            ));
+            assert!(matches!(
+                state.restrict_voxel(0, 1, 2, HashSet::from_all(vec![Tile::RED])),
+                Err(Error::InvalidConstraint { x: 0, y: 5 })
+            ));
+        }
+    }
+
+    mod solver {
+        use rstest::{fixture, rstest};
+        use std::collections::HashSet;
+
+        use crate::{
+            enums::{Direction, Tile},
+            generator::Generator,
+            rules::Rule,
+        };
+
+        /// Any tile can be next to any other, so every step collapses exactly one cell.
+        #[fixture]
+        fn generator_free() -> Generator {
+            let tiles = [Tile::RED, Tile::BLUE];
+            let mut rules = HashSet::new();
+            for direction in Direction::all() {
+                for curr in tiles.iter() {
+                    for adj in tiles.iter() {
+                        rules.insert(Rule::new(curr.clone(), adj.clone(), direction.clone()));
+                    }
+                }
+            }
+            Generator::builder()
+                .size(3, 3)
+                .rules(rules)
+                .seed(5)
+                .build()
+                .unwrap()
+        }
+
+        #[rstest]
+        fn test_step(generator_free: Generator) {
+            let mut solver = generator_free.solver().unwrap();
+            solver.step().unwrap();
+            let collapsed = solver
+                .possible_vals()
+                .cells()
+                .iter()
+                .filter(|tile| tile.len() == 1)
+                .count();
+            assert_eq!(collapsed, 1);
+            assert!(!solver.is_done());
+        }
+
+        #[rstest]
+        fn test_run_until(generator_free: Generator) {
+            let mut solver = generator_free.solver().unwrap();
+            solver.run_until(4).unwrap();
+            assert_eq!(solver.steps(), 4);
+            assert!(!solver.is_done());
+
+            // One step per cell, done as soon as the last one collapses.
+            solver.run_until(100).unwrap();
+            assert_eq!(solver.steps(), 9);
+            assert!(solver.is_done());
+            solver.step().unwrap();
+            assert_eq!(solver.steps(), 9);
+        }
+
+        #[rstest]
+        fn test_same_as_generate(generator_free: Generator) {
+            let mut solver = generator_free.solver().unwrap();
+            solver.run_until(3).unwrap();
+            let state = solver.finish().unwrap();
+            assert_eq!(
+                state.possible_vals.cells(),
+                generator_free.generate().unwrap().possible_vals.cells()
+            );
         }
     }
 }
@@ -0,0 +1,288 @@
+use std::collections::HashSet;
+
+use bmp::Image;
+
+use crate::{
+    enums::{Direction, Tiling},
+    hex,
+    rules::Rule,
+};
+
+/// Which transforms of the sample are assumed to be valid samples too.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Symmetry {
+    /// Only the sample as it is.
+    #[default]
+    None,
+    /// The four quarter turns.
+    Rotations,
+    /// The sample and its mirror image, left and right swapped.
+    HorizontalMirror,
+    /// The eight rotations and reflections of the square.
+    Full,
+}
+
+impl Symmetry {
+    /// The transforms of the class, the identity first.
+    pub fn transforms(&self) -> Vec<Transform> {
+        match self {
+            Symmetry::None => vec![Transform::IDENTITY],
+            Symmetry::Rotations => (0..4).map(|turns| Transform::new(turns, false)).collect(),
+            Symmetry::HorizontalMirror => vec![Transform::IDENTITY, Transform::new(0, true)],
+            Symmetry::Full => Transform::all().to_vec(),
+        }
+    }
+
+    /// The transforms of the class on a hex tiling, as the `turns` and `mirrored`
+    /// arguments of `hex::transform_direction`: sixth turns instead of quarter turns.
+    pub fn hex_transforms(&self) -> Vec<(u8, bool)> {
+        match self {
+            Symmetry::None => vec![(0, false)],
+            Symmetry::Rotations => (0..6).map(|turns| (turns, false)).collect(),
+            Symmetry::HorizontalMirror => vec![(0, false), (0, true)],
+            Symmetry::Full => (0..12).map(|i| (i % 6, i >= 6)).collect(),
+        }
+    }
+}
+
+/// One of the 8 rotations and reflections of the square: a mirror swapping left and
+/// right when `mirrored`, then `turns` quarter turns clockwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Transform {
+    pub turns: u8,
+    pub mirrored: bool,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        turns: 0,
+        mirrored: false,
+    };
+
+    pub fn new(turns: u8, mirrored: bool) -> Self {
+        Transform {
+            turns: turns % 4,
+            mirrored,
+        }
+    }
+
+    pub fn all() -> [Transform; 8] {
+        let mut all = [Transform::IDENTITY; 8];
+        for (i, transform) in all.iter_mut().enumerate() {
+            *transform = Transform::new(i as u8 % 4, i >= 4);
+        }
+        all
+    }
+
+    /// Offset (`dx`, `dy`) once transformed, with `y` pointing down.
+    pub fn offset(&self, (dx, dy): (i32, i32)) -> (i32, i32) {
+        let (mut dx, mut dy) = if self.mirrored { (-dx, dy) } else { (dx, dy) };
+        for _ in 0..self.turns {
+            (dx, dy) = (-dy, dx);
+        }
+        (dx, dy)
+    }
+
+    /// Where `direction` points once transformed. The layers above and below are left
+    /// alone, each layer is transformed on its own, and so are the graph edges.
+    pub fn direction(&self, direction: &Direction) -> Direction {
+        match direction {
+            Direction::Above | Direction::Below | Direction::Out(_) | Direction::In(_) => {
+                direction.clone()
+            }
+            _ => Direction::from_offset(self.offset(direction.offset()))
+                .expect("Transforms map directions onto directions"),
+        }
+    }
+
+    /// Size of a `width` by `height` grid once transformed.
+    // `is_multiple_of` would need Rust 1.87.
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn size(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        if self.turns % 2 == 0 {
+            (width, height)
+        } else {
+            (height, width)
+        }
+    }
+
+    /// Where the cell at (`x`, `y`) of a `width` by `height` grid ends up.
+    pub fn coords(&self, (x, y): (u32, u32), (width, height): (u32, u32)) -> (u32, u32) {
+        let (mut x, mut y) = if self.mirrored {
+            (width - 1 - x, y)
+        } else {
+            (x, y)
+        };
+        let (mut w, mut h) = (width, height);
+        for _ in 0..self.turns {
+            (x, y) = (h - 1 - y, x);
+            (w, h) = (h, w);
+        }
+        (x, y)
+    }
+
+    pub fn image(&self, img: &Image) -> Image {
+        let size = (img.get_width(), img.get_height());
+        let (w, h) = self.size(size);
+        let mut transformed = Image::new(w, h);
+        for (x, y) in img.coordinates() {
+            let (tx, ty) = self.coords((x, y), size);
+            transformed.set_pixel(tx, ty, img.get_pixel(x, y));
+        }
+        transformed
+    }
+}
+
+/// Adds the rules the transformed samples would yield. Tiles stand for single colors,
+/// which look the same under every transform, so only the directions change.
+///
+/// The directions are turned as on a square grid, hex rules need
+/// `augment_rules_with_tiling`.
+pub fn augment_rules(rules: &HashSet<Rule>, symmetry: Symmetry) -> HashSet<Rule> {
+    let mut augmented = HashSet::new();
+    for transform in symmetry.transforms() {
+        for rule in rules {
+            augmented.insert(Rule::new(
+                rule.curr_tile.clone(),
+                rule.adj_tile.clone(),
+                transform.direction(&rule.direction),
+            ));
+        }
+    }
+    augmented
+}
+
+/// Like `augment_rules`, for rules learnt on `tiling`. A hexagon has six sides, so on
+/// a hex tiling the rotations are sixth turns.
+pub fn augment_rules_with_tiling(
+    rules: &HashSet<Rule>,
+    symmetry: Symmetry,
+    tiling: Tiling,
+) -> HashSet<Rule> {
+    if tiling == Tiling::Square {
+        return augment_rules(rules, symmetry);
+    }
+    let mut augmented = HashSet::new();
+    for (turns, mirrored) in symmetry.hex_transforms() {
+        for rule in rules {
+            augmented.insert(Rule::new(
+                rule.curr_tile.clone(),
+                rule.adj_tile.clone(),
+                hex::transform_direction(&rule.direction, turns, mirrored),
+            ));
+        }
+    }
+    augmented
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLUE, LIME, RED, WHITE};
+
+    use crate::{enums::Tile, palette::Palette, rules::extract_rules, state::HashSetExt};
+
+    use super::*;
+
+    #[test]
+    fn test_direction() {
+        let quarter = Transform::new(1, false);
+        assert_eq!(quarter.direction(&Direction::Up), Direction::Right);
+        assert_eq!(quarter.direction(&Direction::Right), Direction::Down);
+        let mirror = Transform::new(0, true);
+        assert_eq!(mirror.direction(&Direction::Left), Direction::Right);
+        assert_eq!(mirror.direction(&Direction::Up), Direction::Up);
+
+        for transform in Transform::all() {
+            let mapped = Direction::all()
+                .iter()
+                .map(|direction| transform.direction(direction))
+                .collect::<HashSet<_>>();
+            assert_eq!(mapped.len(), Direction::all().len());
+        }
+        assert_eq!(Transform::all().iter().collect::<HashSet<_>>().len(), 8);
+    }
+
+    #[test]
+    fn test_image() {
+        // R G
+        // B W
+        let mut img = Image::new(2, 2);
+        img.set_pixel(0, 0, RED);
+        img.set_pixel(1, 0, LIME);
+        img.set_pixel(0, 1, BLUE);
+        img.set_pixel(1, 1, WHITE);
+
+        let turned = Transform::new(1, false).image(&img);
+        assert_eq!(turned.get_pixel(0, 0), BLUE);
+        assert_eq!(turned.get_pixel(1, 0), RED);
+        assert_eq!(turned.get_pixel(1, 1), LIME);
+
+        let wide = Image::new(3, 1);
+        let turned = Transform::new(3, true).image(&wide);
+        assert_eq!((turned.get_width(), turned.get_height()), (1, 3));
+    }
+
+    /// The rules of a transformed sample are the augmented rules of the sample.
+    #[test]
+    fn test_augment_rules() {
+        let mut img = Image::new(3, 2);
+        img.set_pixel(0, 0, RED);
+        img.set_pixel(1, 0, LIME);
+        img.set_pixel(2, 0, LIME);
+        img.set_pixel(0, 1, BLUE);
+        img.set_pixel(1, 1, BLUE);
+        img.set_pixel(2, 1, RED);
+        let palette = Palette::from_image(&img);
+        let rules = extract_rules(&img, &palette).unwrap();
+
+        assert_eq!(augment_rules(&rules, Symmetry::None), rules);
+        for symmetry in [
+            Symmetry::Rotations,
+            Symmetry::HorizontalMirror,
+            Symmetry::Full,
+        ] {
+            let mut expected = HashSet::new();
+            for transform in symmetry.transforms() {
+                expected.extend(extract_rules(&transform.image(&img), &palette).unwrap());
+            }
+            assert_eq!(augment_rules(&rules, symmetry), expected);
+        }
+
+        let mirrored = augment_rules(
+            &HashSet::new().with(Rule::new(Tile::RED, Tile::BLUE, Direction::Left)),
+            Symmetry::HorizontalMirror,
+        );
+        assert!(mirrored.contains(&Rule::new(Tile::RED, Tile::BLUE, Direction::Right)));
+        assert_eq!(mirrored.len(), 2);
+    }
+
+    #[test]
+    fn test_augment_hex_rules() {
+        let rules = HashSet::new().with(Rule::new(Tile::RED, Tile::BLUE, Direction::Right));
+        assert_eq!(
+            augment_rules_with_tiling(&rules, Symmetry::Rotations, Tiling::Square),
+            augment_rules(&rules, Symmetry::Rotations)
+        );
+
+        // Every side of the hexagon, and nothing a hexagon does not have.
+        let expected = Direction::hex()
+            .into_iter()
+            .map(|direction| Rule::new(Tile::RED, Tile::BLUE, direction))
+            .collect::<HashSet<_>>();
+        for symmetry in [Symmetry::Rotations, Symmetry::Full] {
+            assert_eq!(
+                augment_rules_with_tiling(&rules, symmetry, Tiling::Hex),
+                expected
+            );
+        }
+
+        let diagonal = HashSet::new().with(Rule::new(Tile::RED, Tile::BLUE, Direction::UpLeft));
+        let mirrored =
+            augment_rules_with_tiling(&diagonal, Symmetry::HorizontalMirror, Tiling::Hex);
+        assert_eq!(
+            mirrored,
+            diagonal.with(Rule::new(Tile::RED, Tile::BLUE, Direction::UpRight))
+        );
+        assert_eq!(Symmetry::Full.hex_transforms().len(), 12);
+    }
+}
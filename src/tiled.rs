@@ -0,0 +1,218 @@
+use std::collections::HashSet;
+
+use bmp::Image;
+
+use crate::{
+    enums::{Direction, Tile},
+    error::{Error, Result},
+    rules::Rule,
+    state::{Render, State},
+};
+
+/// Socket labels on each edge of a sprite. Two sprites can be placed side by
+/// side when the labels of the edges that touch are equal.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Sockets {
+    pub up: String,
+    pub down: String,
+    pub left: String,
+    pub right: String,
+}
+
+impl Sockets {
+    pub fn new(up: &str, down: &str, left: &str, right: &str) -> Self {
+        Sockets {
+            up: up.to_string(),
+            down: down.to_string(),
+            left: left.to_string(),
+            right: right.to_string(),
+        }
+    }
+
+    /// The label of the edge facing `direction`, `None` for a corner.
+    pub fn get(&self, direction: &Direction) -> Option<&str> {
+        match direction {
+            Direction::Up => Some(&self.up),
+            Direction::Down => Some(&self.down),
+            Direction::Left => Some(&self.left),
+            Direction::Right => Some(&self.right),
+            _ => None,
+        }
+    }
+}
+
+/// The simple tiled model: sprites cut from a sprite sheet, where `Tile(i)`
+/// is the `i`-th sprite read row by row from the top-left of the sheet.
+#[derive(Clone, Debug)]
+pub struct Tileset {
+    pub tile_size: u32,
+    pub sprites: Vec<Image>,
+    pub sockets: Vec<Sockets>,
+}
+
+impl Tileset {
+    /// Cuts `sheet` into `tile_size`×`tile_size` sprites, one `Sockets` per sprite.
+    /// Fails with `Error::InvalidDimensions` when the sheet is not a whole number of
+    /// tiles or when the socket count does not match the sprite count.
+    // `is_multiple_of` would need Rust 1.87.
+    #[allow(clippy::manual_is_multiple_of)]
+    pub fn from_sprite_sheet(sheet: &Image, tile_size: u32, sockets: Vec<Sockets>) -> Result<Self> {
+        let w = sheet.get_width();
+        let h = sheet.get_height();
+        let invalid = Error::InvalidDimensions {
+            width: w,
+            height: h,
+            depth: 1,
+        };
+        if tile_size == 0 || w % tile_size != 0 || h % tile_size != 0 {
+            return Err(invalid);
+        }
+
+        let mut sprites = Vec::new();
+        for tile_y in 0..(h / tile_size) {
+            for tile_x in 0..(w / tile_size) {
+                let mut sprite = Image::new(tile_size, tile_size);
+                for (x, y) in sprite.coordinates() {
+                    sprite.set_pixel(
+                        x,
+                        y,
+                        sheet.get_pixel(tile_x * tile_size + x, tile_y * tile_size + y),
+                    );
+                }
+                sprites.push(sprite);
+            }
+        }
+        if sprites.len() != sockets.len() {
+            return Err(invalid);
+        }
+
+        Ok(Tileset {
+            tile_size,
+            sprites,
+            sockets,
+        })
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn extract_rules(&self) -> HashSet<Rule> {
+        let mut rules = HashSet::new();
+        for (i, curr) in self.sockets.iter().enumerate() {
+            for (j, adj) in self.sockets.iter().enumerate() {
+                for direction in Direction::all().iter() {
+                    if curr.get(direction) == adj.get(&direction.opposite()) {
+                        rules.insert(Rule::new(Tile(j), Tile(i), direction.clone()));
+                    }
+                }
+            }
+        }
+        rules
+    }
+}
+
+impl Render for Tileset {
+    /// Blits the sprite of every cell of a collapsed state into one image.
+    fn render(&self, state: &State) -> Result<Image> {
+        state.check_single_layer()?;
+        let mut img = Image::new(
+            state.width as u32 * self.tile_size,
+            state.height as u32 * self.tile_size,
+        );
+        for x in 0..state.width {
+            for y in 0..state.height {
+                let possibilities = state.get(x, y);
+                if possibilities.len() != 1 {
+                    return Err(Error::Unrenderable);
+                }
+                let tile = possibilities.iter().next().ok_or(Error::Unrenderable)?;
+                let sprite = self.sprites.get(tile.id()).ok_or(Error::Unrenderable)?;
+                for (sprite_x, sprite_y) in sprite.coordinates() {
+                    img.set_pixel(
+                        x as u32 * self.tile_size + sprite_x,
+                        y as u32 * self.tile_size + sprite_y,
+                        sprite.get_pixel(sprite_x, sprite_y),
+                    );
+                }
+            }
+        }
+        Ok(img)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLACK, WHITE};
+
+    use crate::state::HashSetExt;
+
+    use super::*;
+
+    /// Two 2×2 sprites side by side: a blank one and a horizontal road.
+    fn sheet() -> Image {
+        let mut img = Image::new(4, 2);
+        for (x, y) in img.coordinates() {
+            img.set_pixel(x, y, if x >= 2 && y == 1 { BLACK } else { WHITE });
+        }
+        img
+    }
+
+    fn sockets() -> Vec<Sockets> {
+        vec![
+            Sockets::new("grass", "grass", "grass", "grass"),
+            Sockets::new("grass", "grass", "road", "road"),
+        ]
+    }
+
+    #[test]
+    fn test_from_sprite_sheet() {
+        let tileset = Tileset::from_sprite_sheet(&sheet(), 2, sockets()).unwrap();
+        assert_eq!(tileset.tile_count(), 2);
+        assert_eq!(tileset.sprites[0].get_pixel(0, 1), WHITE);
+        assert_eq!(tileset.sprites[1].get_pixel(0, 1), BLACK);
+    }
+
+    #[test]
+    fn test_from_sprite_sheet_invalid() {
+        assert!(Tileset::from_sprite_sheet(&sheet(), 3, sockets()).is_err());
+        assert!(Tileset::from_sprite_sheet(&sheet(), 0, sockets()).is_err());
+        assert!(Tileset::from_sprite_sheet(&sheet(), 2, vec![]).is_err());
+    }
+
+    #[test]
+    fn test_extract_rules() {
+        let tileset = Tileset::from_sprite_sheet(&sheet(), 2, sockets()).unwrap();
+        let rules = tileset.extract_rules();
+        let expected = HashSet::from_all(vec![
+            Rule::new(Tile(0), Tile(0), Direction::Up),
+            Rule::new(Tile(0), Tile(0), Direction::Down),
+            Rule::new(Tile(0), Tile(0), Direction::Left),
+            Rule::new(Tile(0), Tile(0), Direction::Right),
+            Rule::new(Tile(1), Tile(1), Direction::Up),
+            Rule::new(Tile(1), Tile(1), Direction::Down),
+            Rule::new(Tile(1), Tile(1), Direction::Left),
+            Rule::new(Tile(1), Tile(1), Direction::Right),
+            Rule::new(Tile(0), Tile(1), Direction::Up),
+            Rule::new(Tile(0), Tile(1), Direction::Down),
+            Rule::new(Tile(1), Tile(0), Direction::Up),
+            Rule::new(Tile(1), Tile(0), Direction::Down),
+        ]);
+        assert_eq!(rules, expected);
+    }
+
+    #[test]
+    fn test_render() {
+        let tileset = Tileset::from_sprite_sheet(&sheet(), 2, sockets()).unwrap();
+        let mut state = State::new(2, 1, &HashSet::from_all(vec![Tile(0), Tile(1)]));
+        state.possible_vals.set(0, 0, HashSet::new().with(Tile(1)));
+        state.possible_vals.set(1, 0, HashSet::new().with(Tile(0)));
+
+        let img = tileset.render(&state).unwrap();
+        assert_eq!(img.get_width(), 4);
+        assert_eq!(img.get_height(), 2);
+        assert_eq!(img.get_pixel(1, 1), BLACK);
+        assert_eq!(img.get_pixel(3, 1), WHITE);
+        assert_eq!(img.get_pixel(0, 0), WHITE);
+    }
+}
@@ -0,0 +1,395 @@
+use std::{collections::HashSet, fmt::Debug};
+
+use crate::{
+    enums::{Direction, Tile, Tiling},
+    error::{Error, Result},
+    hex,
+    rules::Rule,
+};
+
+/// How the cells are connected. A cell is addressed by (`x`, `y`), `x` below the first
+/// value of `size` and `y` below the second one.
+pub trait Topology: Debug + Send + Sync {
+    /// Columns and rows of cells.
+    fn size(&self) -> (usize, usize);
+
+    /// Every neighbour of `cell`, with the direction leading to it.
+    fn neighbours(&self, cell: (usize, usize)) -> Vec<(Direction, (usize, usize))>;
+
+    /// Calls `visit` on every neighbour of `cell` in the order of `neighbours`, without
+    /// collecting them, which keeps allocations out of the inner loop of `propagate`.
+    fn for_each_neighbour(
+        &self,
+        cell: (usize, usize),
+        visit: &mut dyn FnMut(&Direction, (usize, usize)),
+    ) {
+        for (direction, neighbour) in self.neighbours(cell) {
+            visit(&direction, neighbour);
+        }
+    }
+
+    /// The directions a cell can have neighbours in.
+    fn directions(&self) -> Vec<Direction>;
+
+    /// Width, rows of a single layer and number of layers.
+    fn dimensions(&self) -> (usize, usize, usize) {
+        let (width, height) = self.size();
+        (width, height, 1)
+    }
+
+    /// The grid behind the topology, if it is one.
+    fn grid(&self) -> Option<&Grid> {
+        None
+    }
+}
+
+/// `depth` layers of `width` by `height` cells, stacked along `y`: the cell
+/// (`x`, `y`, `z`) is (`x`, `z * height + y`), so a 2D grid is the special case of a
+/// single layer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Grid {
+    pub width: usize,
+    pub height: usize,
+    pub depth: usize,
+    /// Whether the opposite edges of each layer are neighbours, so the result tiles.
+    /// The first and last layers never are.
+    pub periodic: bool,
+    pub tiling: Tiling,
+}
+
+impl Grid {
+    /// A single layer of square cells.
+    pub fn new(width: usize, height: usize) -> Self {
+        Grid {
+            width,
+            height,
+            depth: 1,
+            periodic: false,
+            tiling: Tiling::Square,
+        }
+    }
+
+    /// Coordinates of the cell next to (`x`, `y`) in `direction`, if inside the grid.
+    /// A periodic grid wraps around within each layer, so only the bottom and top
+    /// layers miss a neighbour.
+    pub fn neighbour(
+        &self,
+        (x, y): (usize, usize),
+        direction: &Direction,
+    ) -> Option<(usize, usize)> {
+        let (w, h, d) = (self.width, self.height, self.depth);
+        if w == 0 || h == 0 {
+            return None;
+        }
+        let (dx, dy, dz) = direction.offset3();
+        let (x, y, z) = (x as i64, (y % h) as i64, (y / h) as i64);
+        let (nx, ny) = match self.tiling {
+            Tiling::Square => (x + dx as i64, y + dy as i64),
+            Tiling::Hex if dz != 0 => (x, y),
+            Tiling::Hex => hex::step((x, y), direction)?,
+        };
+        let nz = z + dz as i64;
+        if nz < 0 || nz >= d as i64 {
+            return None;
+        }
+        let (nx, ny) = if self.periodic {
+            (nx.rem_euclid(w as i64), ny.rem_euclid(h as i64))
+        } else {
+            if nx < 0 || ny < 0 || nx >= w as i64 || ny >= h as i64 {
+                return None;
+            }
+            (nx, ny)
+        };
+        Some((nx as usize, nz as usize * h + ny as usize))
+    }
+}
+
+impl Topology for Grid {
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height * self.depth)
+    }
+
+    fn neighbours(&self, cell: (usize, usize)) -> Vec<(Direction, (usize, usize))> {
+        let mut neighbours = Vec::new();
+        self.for_each_neighbour(cell, &mut |direction, neighbour| {
+            neighbours.push((direction.clone(), neighbour))
+        });
+        neighbours
+    }
+
+    fn for_each_neighbour(
+        &self,
+        cell: (usize, usize),
+        visit: &mut dyn FnMut(&Direction, (usize, usize)),
+    ) {
+        // Same order as `directions`, without building it.
+        let mut visit_towards = |direction: Direction| {
+            if let Some(neighbour) = self.neighbour(cell, &direction) {
+                visit(&direction, neighbour);
+            }
+        };
+        match self.tiling {
+            Tiling::Square => Direction::moore().into_iter().for_each(&mut visit_towards),
+            Tiling::Hex => Direction::hex().into_iter().for_each(&mut visit_towards),
+        }
+        if self.depth > 1 {
+            [Direction::Above, Direction::Below]
+                .into_iter()
+                .for_each(visit_towards);
+        }
+    }
+
+    fn directions(&self) -> Vec<Direction> {
+        let mut directions = self.tiling.directions();
+        if self.depth > 1 {
+            directions.extend([Direction::Above, Direction::Below]);
+        }
+        directions
+    }
+
+    fn dimensions(&self) -> (usize, usize, usize) {
+        (self.width, self.height, self.depth)
+    }
+
+    fn grid(&self) -> Option<&Grid> {
+        Some(self)
+    }
+}
+
+/// Nodes joined by labelled edges, laid out as a single row: node `i` is the cell
+/// (`i`, 0). The label of an edge names the relation it stands for, and the rules
+/// refer to it through `Direction::Out` and `Direction::In`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Graph {
+    edges: Vec<Vec<(Direction, usize)>>,
+}
+
+impl Graph {
+    pub fn new(node_count: usize) -> Self {
+        Graph {
+            edges: vec![Vec::new(); node_count],
+        }
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// Adds an edge labelled `label` from `from` to `to`: `to` is then at
+    /// `Direction::Out(label)` of `from`, and `from` at `Direction::In(label)` of `to`.
+    /// A node can have any number of edges with the same label. Fails with
+    /// `Error::OutOfBounds` when either node is not in the graph.
+    pub fn add_edge(&mut self, from: usize, to: usize, label: usize) -> Result<()> {
+        for node in [from, to] {
+            if node >= self.node_count() {
+                return Err(Error::OutOfBounds { x: node, y: 0 });
+            }
+        }
+        self.edges[from].push((Direction::Out(label), to));
+        self.edges[to].push((Direction::In(label), from));
+        Ok(())
+    }
+
+    /// Learns the rules of a sample graph where node `i` holds `tiles[i]`. Fails with
+    /// `Error::InvalidDimensions` when there is not exactly one tile per node.
+    pub fn extract_rules(&self, tiles: &[Tile]) -> Result<HashSet<Rule>> {
+        if tiles.len() != self.node_count() {
+            return Err(Error::InvalidDimensions {
+                width: tiles.len() as u32,
+                height: 1,
+                depth: 1,
+            });
+        }
+        let mut rules = HashSet::new();
+        for (node, edges) in self.edges.iter().enumerate() {
+            for (direction, neighbour) in edges {
+                rules.insert(Rule::new(
+                    tiles[*neighbour].clone(),
+                    tiles[node].clone(),
+                    direction.clone(),
+                ));
+            }
+        }
+        Ok(rules)
+    }
+}
+
+impl Topology for Graph {
+    fn size(&self) -> (usize, usize) {
+        (self.node_count(), 1)
+    }
+
+    fn neighbours(&self, (x, _): (usize, usize)) -> Vec<(Direction, (usize, usize))> {
+        self.edges[x]
+            .iter()
+            .map(|(direction, neighbour)| (direction.clone(), (*neighbour, 0)))
+            .collect()
+    }
+
+    fn for_each_neighbour(
+        &self,
+        (x, _): (usize, usize),
+        visit: &mut dyn FnMut(&Direction, (usize, usize)),
+    ) {
+        for (direction, neighbour) in &self.edges[x] {
+            visit(direction, (*neighbour, 0));
+        }
+    }
+
+    fn directions(&self) -> Vec<Direction> {
+        let mut directions = Vec::new();
+        for (direction, _) in self.edges.iter().flatten() {
+            if !directions.contains(direction) {
+                directions.push(direction.clone());
+            }
+        }
+        directions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{domain::Domain, generator::Generator, state::HashSetExt};
+
+    use super::*;
+
+    /// Nodes `0..node_count` in a loop, each one joined to the next.
+    fn cycle(node_count: usize) -> Graph {
+        let mut graph = Graph::new(node_count);
+        for node in 0..node_count {
+            graph.add_edge(node, (node + 1) % node_count, 0).unwrap();
+        }
+        graph
+    }
+
+    #[test]
+    fn test_grid_neighbours() {
+        let grid = Grid {
+            depth: 2,
+            ..Grid::new(3, 2)
+        };
+        assert_eq!(grid.size(), (3, 4));
+        assert_eq!(grid.neighbour((1, 1), &Direction::Above), Some((1, 3)));
+        assert_eq!(grid.neighbour((1, 3), &Direction::Up), Some((1, 2)));
+        assert_eq!(grid.neighbour((1, 2), &Direction::Up), None);
+        assert_eq!(grid.neighbours((0, 0)).len(), 4);
+
+        let periodic = Grid {
+            periodic: true,
+            ..grid
+        };
+        assert_eq!(periodic.neighbour((1, 2), &Direction::Up), Some((1, 3)));
+        // The layers do not wrap: nothing is below the bottom one.
+        assert_eq!(periodic.neighbour((1, 1), &Direction::Below), None);
+        assert_eq!(periodic.neighbours((0, 0)).len(), 9);
+    }
+
+    #[test]
+    fn test_for_each_neighbour() {
+        let grid = Grid {
+            depth: 2,
+            periodic: true,
+            tiling: Tiling::Hex,
+            ..Grid::new(4, 2)
+        };
+        for cell in [(0, 0), (3, 1), (2, 2)] {
+            let mut visited = Vec::new();
+            grid.for_each_neighbour(cell, &mut |direction, neighbour| {
+                visited.push((direction.clone(), neighbour))
+            });
+            let expected = grid
+                .directions()
+                .into_iter()
+                .filter_map(|direction| {
+                    Some((direction.clone(), grid.neighbour(cell, &direction)?))
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(visited, expected);
+        }
+
+        let graph = cycle(3);
+        let mut visited = Vec::new();
+        graph.for_each_neighbour((0, 0), &mut |direction, neighbour| {
+            visited.push((direction.clone(), neighbour))
+        });
+        assert_eq!(visited, graph.neighbours((0, 0)));
+    }
+
+    #[test]
+    fn test_graph() {
+        let graph = cycle(4);
+        assert_eq!(
+            graph.neighbours((1, 0)),
+            vec![(Direction::In(0), (0, 0)), (Direction::Out(0), (2, 0))]
+        );
+        assert_eq!(
+            graph.directions(),
+            vec![Direction::Out(0), Direction::In(0)]
+        );
+
+        let rules = graph
+            .extract_rules(&[Tile::RED, Tile::BLUE, Tile::RED, Tile::BLUE])
+            .unwrap();
+        let expected = HashSet::from_all(vec![
+            Rule::new(Tile::BLUE, Tile::RED, Direction::Out(0)),
+            Rule::new(Tile::RED, Tile::BLUE, Direction::Out(0)),
+            Rule::new(Tile::BLUE, Tile::RED, Direction::In(0)),
+            Rule::new(Tile::RED, Tile::BLUE, Direction::In(0)),
+        ]);
+        assert_eq!(rules, expected);
+        assert!(graph.extract_rules(&[Tile::RED]).is_err());
+
+        let mut graph = cycle(2);
+        assert!(matches!(
+            graph.add_edge(1, 2, 0),
+            Err(Error::OutOfBounds { x: 2, y: 0 })
+        ));
+        assert_eq!(graph, cycle(2));
+    }
+
+    #[test]
+    fn test_generate_graph() {
+        let rules = cycle(2).extract_rules(&[Tile::RED, Tile::BLUE]).unwrap();
+        let state = Generator::builder()
+            .rules(rules.clone())
+            .topology(cycle(6))
+            .seed(4)
+            .build()
+            .unwrap()
+            .generate()
+            .unwrap();
+        assert_eq!((state.width, state.height), (6, 1));
+        for node in 0..6 {
+            assert_ne!(state.get(node, 0), state.get((node + 1) % 6, 0));
+        }
+
+        // An odd loop cannot alternate.
+        let odd = Generator::builder()
+            .rules(rules)
+            .topology(cycle(5))
+            .build()
+            .unwrap();
+        assert!(odd.generate().is_err());
+    }
+
+    #[test]
+    fn test_huge_label() {
+        let mut graph = Graph::new(3);
+        graph.add_edge(0, 1, usize::MAX).unwrap();
+        graph.add_edge(1, 2, usize::MAX / 2).unwrap();
+        let rules = graph
+            .extract_rules(&[Tile::RED, Tile::GREEN, Tile::BLUE])
+            .unwrap();
+        let state = Generator::builder()
+            .rules(rules)
+            .topology(graph)
+            .seed(1)
+            .build()
+            .unwrap()
+            .generate()
+            .unwrap();
+        // Each label allows a single pair of tiles, so only the sample fits.
+        assert_eq!(state.get(0, 0), &Domain::from_iter([Tile::RED]));
+        assert_eq!(state.get(2, 0), &Domain::from_iter([Tile::BLUE]));
+    }
+}
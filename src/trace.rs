@@ -0,0 +1,169 @@
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{enums::Tile, error::Result, observer::Observer, rules::Rule, state::State};
+
+/// Writes the rules into `rules.txt`, then the grid into a numbered `state_<n>_<label>.txt`
+/// at the start of every attempt, at every contradiction and after every backtrack.
+/// Existing files are overwritten but never deleted.
+#[derive(Clone, Debug)]
+pub struct DirTrace {
+    dir: PathBuf,
+    index: u32,
+}
+
+impl DirTrace {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DirTrace {
+            dir: dir.into(),
+            index: 0,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn create(&self, file_name: &str) -> Result<BufWriter<File>> {
+        fs::create_dir_all(&self.dir)?;
+        let mut file_path = self.dir.join(file_name);
+        file_path.set_extension("txt");
+        Ok(BufWriter::new(File::create(file_path)?))
+    }
+
+    fn save_into_file(&mut self, state: &State, end: &str) -> Result<()> {
+        let mut w = self.create(&format!("state_{}_{}", self.index, end))?;
+        self.index += 1;
+        write_state(&mut w, state)?;
+        w.flush()?;
+        Ok(())
+    }
+}
+
+impl Observer for DirTrace {
+    fn on_start(&mut self, rules: &HashSet<Rule>, _state: &State) -> Result<()> {
+        let mut w = self.create("rules")?;
+        write_rules(&mut w, rules)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    fn on_restart(&mut self, attempt: usize, state: &State) -> Result<()> {
+        self.save_into_file(state, &format!("attempt_{}_initial", attempt))
+    }
+
+    fn on_contradiction(&mut self, _coord: (usize, usize), state: &State) -> Result<()> {
+        self.save_into_file(state, "contradiction")
+    }
+
+    fn on_backtrack(&mut self, _coord: (usize, usize), _tile: &Tile, state: &State) -> Result<()> {
+        self.save_into_file(state, "after_backtrack")
+    }
+}
+
+/// Keeps the dumps in memory, in the same text format as `DirTrace`.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryTrace {
+    pub rules: String,
+    /// Label and dump of every state, oldest first.
+    pub states: Vec<(String, String)>,
+}
+
+impl MemoryTrace {
+    fn save(&mut self, state: &State, label: &str) -> Result<()> {
+        let mut buf = Vec::new();
+        write_state(&mut buf, state)?;
+        self.states.push((
+            label.to_string(),
+            String::from_utf8_lossy(&buf).into_owned(),
+        ));
+        Ok(())
+    }
+}
+
+impl Observer for MemoryTrace {
+    fn on_start(&mut self, rules: &HashSet<Rule>, _state: &State) -> Result<()> {
+        let mut buf = Vec::new();
+        write_rules(&mut buf, rules)?;
+        self.rules = String::from_utf8_lossy(&buf).into_owned();
+        Ok(())
+    }
+
+    fn on_restart(&mut self, attempt: usize, state: &State) -> Result<()> {
+        self.save(state, &format!("attempt_{}_initial", attempt))
+    }
+
+    fn on_contradiction(&mut self, _coord: (usize, usize), state: &State) -> Result<()> {
+        self.save(state, "contradiction")
+    }
+
+    fn on_backtrack(&mut self, _coord: (usize, usize), _tile: &Tile, state: &State) -> Result<()> {
+        self.save(state, "after_backtrack")
+    }
+}
+
+fn write_rules(w: &mut impl Write, rules: &HashSet<Rule>) -> Result<()> {
+    for rule in rules {
+        writeln!(
+            w,
+            "{:?} can be at {:?} of {:?}",
+            rule.curr_tile, rule.direction, rule.adj_tile
+        )?;
+    }
+    Ok(())
+}
+
+/// One line per row, the possibilities of each cell minified and separated by tabs.
+/// The layers of a 3D grid follow each other, separated by an empty line.
+fn write_state(w: &mut impl Write, state: &State) -> Result<()> {
+    for z in 0..state.depth {
+        if z > 0 {
+            writeln!(w)?;
+        }
+        for y in 0..state.height {
+            for x in 0..state.width {
+                for tile in state.get_voxel(x, y, z) {
+                    write!(w, "{}", tile.minify())?;
+                }
+                write!(w, "\t")?;
+            }
+            writeln!(w)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{enums::Direction, state::HashSetExt};
+
+    use super::*;
+
+    #[test]
+    fn test_memory_trace() {
+        let mut state = State::new(2, 1, &HashSet::from_all(vec![Tile::RED]));
+        state
+            .possible_vals
+            .set(1, 0, HashSet::from_all(vec![Tile::BLUE]));
+
+        let mut trace = MemoryTrace::default();
+        let rules = HashSet::new().with(Rule::new(Tile::RED, Tile::BLUE, Direction::Up));
+        trace.on_start(&rules, &state).unwrap();
+        trace.on_restart(0, &state).unwrap();
+        trace.on_collapse((0, 0), &Tile::RED).unwrap();
+        trace.on_backtrack((0, 0), &Tile::RED, &state).unwrap();
+
+        assert_eq!(trace.rules, "Tile(0) can be at Up of Tile(2)\n");
+        assert_eq!(
+            trace.states,
+            vec![
+                ("attempt_0_initial".to_string(), "0\t2\t\n".to_string()),
+                ("after_backtrack".to_string(), "0\t2\t\n".to_string()),
+            ]
+        );
+    }
+}
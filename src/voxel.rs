@@ -0,0 +1,149 @@
+use std::collections::HashSet;
+
+use bmp::Image;
+
+use crate::{
+    enums::{Direction, Neighbourhood},
+    error::{Error, Result},
+    palette::Palette,
+    rules::{extract_rules_with_neighbourhood, Rule},
+    state::{Render, State},
+};
+
+/// Every color of a stack of slices, in the order `Palette::from_image` would meet
+/// them going from the first slice to the last.
+pub fn palette_from_slices(slices: &[Image]) -> Palette {
+    let mut palette = Palette::new();
+    for slice in slices {
+        for (x, y) in slice.coordinates() {
+            palette.insert(slice.get_pixel(x, y));
+        }
+    }
+    palette
+}
+
+/// Learns the rules of a 3D sample given as one image per layer, the bottom one first.
+///
+/// Each slice yields its own rules within `neighbourhood`, and two pixels at the same
+/// position of consecutive slices are tied by `Direction::Above` and
+/// `Direction::Below`. Fails with `Error::InvalidDimensions` when the slices are not
+/// all the same size and with `Error::InvalidColor` on a color missing from `palette`.
+pub fn extract_rules_from_slices(
+    slices: &[Image],
+    palette: &Palette,
+    neighbourhood: &Neighbourhood,
+) -> Result<HashSet<Rule>> {
+    let Some(first) = slices.first() else {
+        return Ok(HashSet::new());
+    };
+    let (w, h) = (first.get_width(), first.get_height());
+    if slices
+        .iter()
+        .any(|slice| (slice.get_width(), slice.get_height()) != (w, h))
+    {
+        return Err(Error::InvalidDimensions {
+            width: w,
+            height: h,
+            depth: slices.len() as u32,
+        });
+    }
+
+    let mut rules = HashSet::new();
+    for slice in slices {
+        rules.extend(extract_rules_with_neighbourhood(
+            slice,
+            palette,
+            neighbourhood,
+        )?);
+    }
+    for pair in slices.windows(2) {
+        let (below, above) = (&pair[0], &pair[1]);
+        for (x, y) in below.coordinates() {
+            let to_tile = |img: &Image| {
+                palette
+                    .tile(img.get_pixel(x, y))
+                    .expect("Every slice was checked above")
+            };
+            let (lower, upper) = (to_tile(below), to_tile(above));
+            rules.insert(Rule::new(upper.clone(), lower.clone(), Direction::Above));
+            rules.insert(Rule::new(lower, upper, Direction::Below));
+        }
+    }
+    Ok(rules)
+}
+
+/// Renders every layer of a collapsed state, the bottom one first.
+pub fn render_slices(state: &State, renderer: &impl Render) -> Result<Vec<Image>> {
+    (0..state.depth)
+        .map(|z| renderer.render(&state.layer(z)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLUE, RED};
+
+    use crate::{enums::Tile, generator::Generator};
+
+    use super::*;
+
+    fn filled(w: u32, h: u32, pixel: bmp::Pixel) -> Image {
+        let mut img = Image::new(w, h);
+        for (x, y) in img.coordinates() {
+            img.set_pixel(x, y, pixel);
+        }
+        img
+    }
+
+    #[test]
+    fn test_extract_rules_from_slices() {
+        let slices = [filled(2, 2, RED), filled(2, 2, BLUE)];
+        let palette = palette_from_slices(&slices);
+        let rules =
+            extract_rules_from_slices(&slices, &palette, &Neighbourhood::VonNeumann).unwrap();
+
+        assert!(rules.contains(&Rule::new(Tile(1), Tile(0), Direction::Above)));
+        assert!(rules.contains(&Rule::new(Tile(0), Tile(1), Direction::Below)));
+        assert!(!rules.contains(&Rule::new(Tile(0), Tile(1), Direction::Above)));
+        assert!(!rules.contains(&Rule::new(Tile(0), Tile(1), Direction::Right)));
+        assert_eq!(rules.len(), 10);
+
+        let mismatched = [filled(2, 2, RED), filled(3, 2, BLUE)];
+        assert!(matches!(
+            extract_rules_from_slices(&mismatched, &palette, &Neighbourhood::VonNeumann),
+            Err(Error::InvalidDimensions { depth: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_generate_slices() {
+        let slices = [filled(2, 2, RED), filled(2, 2, BLUE)];
+        let palette = palette_from_slices(&slices);
+        let rules =
+            extract_rules_from_slices(&slices, &palette, &Neighbourhood::VonNeumann).unwrap();
+        let builder = Generator::builder().size(3, 2).rules(rules).seed(1);
+
+        let state = builder
+            .clone()
+            .depth(2)
+            .build()
+            .unwrap()
+            .generate()
+            .unwrap();
+        assert_eq!((state.width, state.height, state.depth), (3, 2, 2));
+        let rendered = render_slices(&state, &palette).unwrap();
+        assert_eq!(rendered, vec![filled(3, 2, RED), filled(3, 2, BLUE)]);
+        assert!(matches!(
+            palette.render(&state),
+            Err(Error::InvalidDimensions { depth: 2, .. })
+        ));
+        let generator = builder.clone().depth(2).build().unwrap();
+        assert!(matches!(
+            generator.generate_image(&palette),
+            Err(Error::InvalidDimensions { depth: 2, .. })
+        ));
+
+        // Nothing was ever seen above the blue layer.
+        assert!(builder.depth(3).build().unwrap().generate().is_err());
+    }
+}
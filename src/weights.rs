@@ -0,0 +1,95 @@
+use std::collections::HashMap;
+
+use bmp::Image;
+
+use crate::{
+    enums::Tile,
+    error::{Error, Result},
+    palette::Palette,
+};
+
+/// Relative frequency of each tile, used when collapsing a cell.
+///
+/// Tiles without an explicit weight count as `1.0`, so an empty `Weights`
+/// gives a uniform choice and hand-made overrides only need the tiles they change.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Weights {
+    inner: HashMap<Tile, f64>,
+}
+
+impl Weights {
+    pub fn new() -> Self {
+        Weights::default()
+    }
+
+    pub fn get(&self, tile: &Tile) -> f64 {
+        self.inner.get(tile).copied().unwrap_or(1.0)
+    }
+
+    pub fn set(&mut self, tile: Tile, weight: f64) {
+        self.inner.insert(tile, weight.max(0.0));
+    }
+
+    pub fn with(mut self, tile: Tile, weight: f64) -> Self {
+        self.set(tile, weight);
+        self
+    }
+
+    pub fn add(&mut self, tile: Tile, weight: f64) {
+        let curr = self.inner.get(&tile).copied().unwrap_or(0.0);
+        self.set(tile, curr + weight);
+    }
+}
+
+impl From<HashMap<Tile, f64>> for Weights {
+    fn from(inner: HashMap<Tile, f64>) -> Self {
+        let mut weights = Weights::new();
+        for (tile, weight) in inner {
+            weights.set(tile, weight);
+        }
+        weights
+    }
+}
+
+/// Counts how many pixels of `img` use each tile of `palette`. Fails with
+/// `Error::InvalidColor` on the first pixel whose color is not in `palette`.
+pub fn extract_weights(img: &Image, palette: &Palette) -> Result<Weights> {
+    let mut weights = Weights::new();
+    for (x, y) in img.coordinates() {
+        let pixel = img.get_pixel(x, y);
+        let tile = palette
+            .tile(pixel)
+            .ok_or(Error::InvalidColor { x, y, pixel })?;
+        weights.add(tile, 1.0);
+    }
+    Ok(weights)
+}
+
+#[cfg(test)]
+mod tests {
+    use bmp::consts::{BLUE, RED};
+
+    use super::*;
+
+    #[test]
+    fn test_extract_weights() {
+        let mut img = Image::new(2, 2);
+        img.set_pixel(0, 0, RED);
+        img.set_pixel(1, 0, BLUE);
+        img.set_pixel(0, 1, RED);
+        img.set_pixel(1, 1, RED);
+        let palette = Palette::from_image(&img);
+
+        let weights = extract_weights(&img, &palette).unwrap();
+        assert_eq!(weights.get(&Tile(0)), 3.0);
+        assert_eq!(weights.get(&Tile(1)), 1.0);
+    }
+
+    #[test]
+    fn test_override() {
+        let weights = Weights::new().with(Tile(0), 5.0).with(Tile(1), -2.0);
+        assert_eq!(weights.get(&Tile(0)), 5.0);
+        assert_eq!(weights.get(&Tile(1)), 0.0);
+        assert_eq!(weights.get(&Tile(2)), 1.0);
+    }
+}